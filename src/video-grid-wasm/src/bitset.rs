@@ -0,0 +1,151 @@
+/// Fixed-size set of video indices backed by 64-bit words
+///
+/// Indices are positions in the engine's `videos` vector, so filter rules
+/// that can be answered from an index combine with word-wide AND/OR/ANDNOT
+/// instead of a per-video check.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitSet {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl BitSet {
+    /// Empty set over `len` indices
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    /// Set containing every index below `len`
+    pub fn full(len: usize) -> Self {
+        let mut set = Self {
+            words: vec![u64::MAX; len.div_ceil(64)],
+            len,
+        };
+        set.clear_tail();
+        set
+    }
+
+    /// Set of the given (in-range) indices
+    pub fn from_indices(len: usize, indices: &[u32]) -> Self {
+        let mut set = Self::new(len);
+        for &idx in indices {
+            set.insert(idx as usize);
+        }
+        set
+    }
+
+    /// Grow or shrink to `len` indices; new indices are not in the set
+    pub fn resize(&mut self, len: usize) {
+        self.words.resize(len.div_ceil(64), 0);
+        self.len = len;
+        self.clear_tail();
+    }
+
+    #[inline]
+    pub fn contains(&self, idx: usize) -> bool {
+        idx < self.len && self.words[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    #[inline]
+    pub fn insert(&mut self, idx: usize) {
+        self.words[idx / 64] |= 1 << (idx % 64);
+    }
+
+    #[inline]
+    pub fn remove(&mut self, idx: usize) {
+        self.words[idx / 64] &= !(1 << (idx % 64));
+    }
+
+    #[inline]
+    pub fn set(&mut self, idx: usize, value: bool) {
+        if value {
+            self.insert(idx);
+        } else {
+            self.remove(idx);
+        }
+    }
+
+    /// Keep only indices also in `other` (AND)
+    pub fn intersect_with(&mut self, other: &BitSet) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= other;
+        }
+        // Indices past the end of `other` are not in it
+        let shared = self.words.len().min(other.words.len());
+        self.words[shared..].fill(0);
+    }
+
+    /// Add every index in `other` (OR)
+    pub fn union_with(&mut self, other: &BitSet) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+        self.clear_tail();
+    }
+
+    /// Drop every index in `other` (ANDNOT)
+    pub fn difference_with(&mut self, other: &BitSet) {
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word &= !other;
+        }
+    }
+
+    /// Number of indices in the set
+    #[cfg(test)]
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Indices in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            let mut word = word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(i * 64 + bit)
+            })
+        })
+    }
+
+    /// Zero the unused bits of the last word
+    fn clear_tail(&mut self) {
+        let used = self.len % 64;
+        if used != 0 {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1 << used) - 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_operations() {
+        let mut a = BitSet::full(130);
+        assert_eq!(a.count(), 130);
+
+        let b = BitSet::from_indices(130, &[0, 64, 65, 129]);
+        a.difference_with(&b);
+        assert_eq!(a.count(), 126);
+        assert!(!a.contains(64));
+        assert!(a.contains(63));
+
+        let mut c = BitSet::from_indices(130, &[1, 64, 100]);
+        c.intersect_with(&a);
+        assert_eq!(c.iter().collect::<Vec<_>>(), vec![1, 100]);
+
+        c.union_with(&b);
+        assert_eq!(c.iter().collect::<Vec<_>>(), vec![0, 1, 64, 65, 100, 129]);
+        assert!(!c.contains(130));
+    }
+}
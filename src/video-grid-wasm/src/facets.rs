@@ -0,0 +1,513 @@
+use crate::bitset::BitSet;
+use crate::filter::{folder_of, Evaluator};
+use crate::types::{FacetCounts, FacetValue, FilterRule, VideoItem};
+use ahash::{AHashMap, AHashSet};
+
+/// Resolution buckets by the shorter frame side, in display order
+const RESOLUTION_BUCKETS: [(&str, u32); 5] = [
+    ("4k", 2160),
+    ("1440p", 1440),
+    ("1080p", 1080),
+    ("720p", 720),
+    ("sd", 0),
+];
+const UNKNOWN: &str = "unknown";
+const UNRATED: &str = "unrated";
+
+/// Filter videos and count facets in a single pass
+///
+/// For every facet value two numbers are reported:
+/// - `count`: videos in the current result carrying the value
+/// - `toggled`: size of the result if the value were toggled. For folders
+///   and tags this flips the value in the selection (inactive tags are
+///   added to `tags_any` when it is in use, otherwise to `tags_all`). For
+///   ratings, resolutions and codecs it narrows that filter to the value.
+///
+/// Toggled counts only need the videos that fail nothing but the facet's
+/// own rule. The result comes from the indexed candidates; a facet whose
+/// rule is active takes one more candidate pass with that rule relaxed.
+pub fn filter_with_facets(
+    evaluator: &Evaluator,
+    videos: &[VideoItem],
+) -> (Vec<usize>, FacetCounts) {
+    let residual = evaluator.residual_rules();
+    // Videos passing every rule outside the `relaxed` mask
+    let passing = |relaxed: u32| -> Vec<usize> {
+        evaluator
+            .candidates_relaxing(videos, relaxed)
+            .iter()
+            .filter(|&idx| {
+                residual.iter().all(|&rule| {
+                    relaxed & rule.bit() != 0 || evaluator.passes(rule, idx, &videos[idx])
+                })
+            })
+            .collect()
+    };
+
+    let filtered = passing(0);
+    let mut in_result = BitSet::new(videos.len());
+    for &idx in &filtered {
+        in_result.insert(idx);
+    }
+
+    // Visit the videos a facet counts, flagging those in the result
+    let visit = |rules: u32, add: &mut dyn FnMut(usize, bool)| {
+        let active = FilterRule::ALL
+            .iter()
+            .any(|&rule| rules & rule.bit() != 0 && evaluator.is_active(rule));
+        if active {
+            for idx in passing(rules) {
+                add(idx, in_result.contains(idx));
+            }
+        } else {
+            for &idx in &filtered {
+                add(idx, true);
+            }
+        }
+    };
+
+    let mut folders = FolderFacet::default();
+    let mut tags = TagFacet::new(evaluator);
+    let mut ratings = ValueFacet::default();
+    let mut resolutions = ValueFacet::default();
+    let mut codecs = ValueFacet::default();
+
+    visit(FilterRule::Folder.bit(), &mut |idx, passed| {
+        folders.add(idx, &videos[idx], passed)
+    });
+    visit(FilterRule::Tags.bit(), &mut |idx, passed| tags.add(&videos[idx], passed));
+    visit(FilterRule::Rating.bit(), &mut |idx, passed| {
+        ratings.add(rating_bucket(&videos[idx]), passed)
+    });
+    visit(FilterRule::Width.bit() | FilterRule::Height.bit(), &mut |idx, passed| {
+        resolutions.add(resolution_bucket(&videos[idx]), passed)
+    });
+    visit(FilterRule::Codec.bit(), &mut |idx, passed| {
+        codecs.add(codec_bucket(&videos[idx]), passed)
+    });
+
+    let total = filtered.len();
+    let counts = FacetCounts {
+        total,
+        folders: folders.finish(evaluator, videos),
+        tags: tags.finish(total),
+        ratings: ratings.finish(|a, b| rating_order(a).cmp(&rating_order(b))),
+        resolutions: resolutions.finish(|a, b| resolution_order(a).cmp(&resolution_order(b))),
+        codecs: codecs.finish(|a, b| a.cmp(b)),
+    };
+
+    (filtered, counts)
+}
+
+/// Facet where toggling narrows the filter to a single value
+#[derive(Default)]
+struct ValueFacet {
+    /// value -> (count in result, count ignoring this facet's rule)
+    values: AHashMap<String, (usize, usize)>,
+}
+
+impl ValueFacet {
+    fn add(&mut self, value: String, passed: bool) {
+        let entry = self.values.entry(value).or_insert((0, 0));
+        if passed {
+            entry.0 += 1;
+        }
+        entry.1 += 1;
+    }
+
+    fn finish(self, order: impl Fn(&str, &str) -> std::cmp::Ordering) -> Vec<FacetValue> {
+        let mut values: Vec<FacetValue> = self
+            .values
+            .into_iter()
+            .map(|(value, (count, toggled))| FacetValue { value, count, toggled })
+            .collect();
+        values.sort_by(|a, b| order(&a.value, &b.value));
+        values
+    }
+}
+
+/// Folder facet keyed like `FilterEngine`'s folder groups: by the scanner
+/// folder, or by the directory of the path for videos without one
+#[derive(Default)]
+struct FolderFacet<'v> {
+    /// folder -> (count in result, count ignoring the folder rule, sample video)
+    buckets: AHashMap<(bool, &'v str), (usize, usize, usize)>,
+}
+
+impl<'v> FolderFacet<'v> {
+    fn add(&mut self, idx: usize, video: &'v VideoItem, passed: bool) {
+        let entry = self.buckets.entry(folder_of(video)).or_insert((0, 0, idx));
+        if passed {
+            entry.0 += 1;
+        }
+        entry.1 += 1;
+    }
+
+    fn finish(self, evaluator: &Evaluator, videos: &[VideoItem]) -> Vec<FacetValue> {
+        let selected = evaluator.selected_folders();
+
+        // A scanner folder and a derived directory may share a name
+        let mut keys: Vec<&str> = self.buckets.keys().map(|&(_, folder)| folder).collect();
+        keys.extend(selected);
+        keys.sort_unstable();
+        keys.dedup();
+
+        keys.into_iter()
+            .map(|key| {
+                // Flip the folder in the selection
+                let mut toggled_selection: Vec<&str> =
+                    selected.iter().copied().filter(|&folder| folder != key).collect();
+                if toggled_selection.len() == selected.len() {
+                    toggled_selection.push(key);
+                }
+
+                // Videos in the same folder share the folder rule outcome
+                let toggled = self
+                    .buckets
+                    .values()
+                    .filter(|&&(_, _, sample)| {
+                        evaluator.matches_folders(&videos[sample], &toggled_selection)
+                    })
+                    .map(|&(_, relaxed, _)| relaxed)
+                    .sum();
+
+                let count = self
+                    .buckets
+                    .iter()
+                    .filter(|&(&(_, folder), _)| folder == key)
+                    .map(|(_, bucket)| bucket.0)
+                    .sum();
+
+                FacetValue {
+                    value: key.to_string(),
+                    count,
+                    toggled,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Tag facet with toggle accounting for all/any/exclude tag sets
+struct TagFacet<'a> {
+    tags_all: AHashSet<&'a str>,
+    tags_any: AHashSet<&'a str>,
+    tags_exclude: AHashSet<&'a str>,
+    /// Tag -> videos in the result carrying it
+    counts: AHashMap<String, usize>,
+    /// Tag -> videos that would join if it were added to `tags_any`
+    gain_add_any: AHashMap<String, usize>,
+    /// Tag -> videos whose only missing `tags_all` entry is this tag
+    gain_remove_all: AHashMap<String, usize>,
+    /// Tag -> videos whose only excluded tag is this tag
+    gain_remove_exclude: AHashMap<String, usize>,
+    /// Tag -> videos in the result whose only `tags_any` hit is this tag
+    drop_remove_any: AHashMap<String, usize>,
+    /// Videos passing `tags_all` and `tags_exclude` (result without `tags_any`)
+    without_any: usize,
+}
+
+impl<'a> TagFacet<'a> {
+    fn new(evaluator: &Evaluator<'a>) -> Self {
+        let criteria = evaluator.criteria();
+        let set = |tags: &'a [String]| tags.iter().map(String::as_str).collect();
+
+        Self {
+            tags_all: set(&criteria.tags_all),
+            tags_any: set(&criteria.tags_any),
+            tags_exclude: set(&criteria.tags_exclude),
+            counts: AHashMap::new(),
+            gain_add_any: AHashMap::new(),
+            gain_remove_all: AHashMap::new(),
+            gain_remove_exclude: AHashMap::new(),
+            drop_remove_any: AHashMap::new(),
+            without_any: 0,
+        }
+    }
+
+    fn add(&mut self, video: &VideoItem, passed: bool) {
+        let has = |tag: &&str| video.tags.iter().any(|t| t == tag);
+
+        let mut missing_all = self.tags_all.iter().filter(|tag| !has(tag));
+        let first_missing = missing_all.next();
+        let missing_count = first_missing.map_or(0, |_| 1 + missing_all.count().min(1));
+
+        let mut excluded = self.tags_exclude.iter().filter(|tag| has(tag));
+        let first_excluded = excluded.next();
+        let excluded_count = first_excluded.map_or(0, |_| 1 + excluded.count().min(1));
+
+        let mut any_hits = self.tags_any.iter().filter(|tag| has(tag));
+        let first_any_hit = any_hits.next();
+        let any_hit_count = first_any_hit.map_or(0, |_| 1 + any_hits.count().min(1));
+        let any_ok = self.tags_any.is_empty() || any_hit_count > 0;
+
+        if passed {
+            for tag in &video.tags {
+                *self.counts.entry(tag.clone()).or_insert(0) += 1;
+            }
+            if any_hit_count == 1 {
+                bump(&mut self.drop_remove_any, first_any_hit);
+            }
+        }
+
+        if missing_count == 0 && excluded_count == 0 {
+            self.without_any += 1;
+            if !any_ok {
+                for tag in &video.tags {
+                    *self.gain_add_any.entry(tag.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        if missing_count == 1 && excluded_count == 0 && any_ok {
+            bump(&mut self.gain_remove_all, first_missing);
+        }
+
+        if excluded_count == 1 && missing_count == 0 && any_ok {
+            bump(&mut self.gain_remove_exclude, first_excluded);
+        }
+    }
+
+    fn finish(self, total: usize) -> Vec<FacetValue> {
+        let get = |map: &AHashMap<String, usize>, tag: &str| map.get(tag).copied().unwrap_or(0);
+
+        let mut keys: Vec<&str> = self
+            .counts
+            .keys()
+            .chain(self.gain_add_any.keys())
+            .map(String::as_str)
+            .chain(self.tags_all.iter().copied())
+            .chain(self.tags_any.iter().copied())
+            .chain(self.tags_exclude.iter().copied())
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+
+        let mut values: Vec<FacetValue> = keys
+            .into_iter()
+            .map(|tag| {
+                let toggled = if self.tags_all.contains(tag) {
+                    total + get(&self.gain_remove_all, tag)
+                } else if self.tags_exclude.contains(tag) {
+                    total + get(&self.gain_remove_exclude, tag)
+                } else if self.tags_any.contains(tag) {
+                    if self.tags_any.len() == 1 {
+                        self.without_any
+                    } else {
+                        total - get(&self.drop_remove_any, tag)
+                    }
+                } else if !self.tags_any.is_empty() {
+                    total + get(&self.gain_add_any, tag)
+                } else {
+                    get(&self.counts, tag)
+                };
+
+                FacetValue {
+                    value: tag.to_string(),
+                    count: get(&self.counts, tag),
+                    toggled,
+                }
+            })
+            .collect();
+
+        // Tag cloud order: heaviest first
+        values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        values
+    }
+}
+
+#[inline]
+fn bump(map: &mut AHashMap<String, usize>, tag: Option<&&str>) {
+    if let Some(tag) = tag {
+        *map.entry(tag.to_string()).or_insert(0) += 1;
+    }
+}
+
+fn rating_bucket(video: &VideoItem) -> String {
+    match video.rating {
+        Some(rating) => rating.to_string(),
+        None => UNRATED.to_string(),
+    }
+}
+
+/// Highest rating first, unrated last
+fn rating_order(value: &str) -> i32 {
+    value.parse::<i32>().map_or(0, |rating| -rating)
+}
+
+fn resolution_bucket(video: &VideoItem) -> String {
+    let short_side = match (video.width, video.height) {
+        (Some(width), Some(height)) => width.min(height),
+        (None, Some(height)) => height,
+        _ => return UNKNOWN.to_string(),
+    };
+
+    RESOLUTION_BUCKETS
+        .iter()
+        .find(|&&(_, min)| short_side >= min)
+        .map_or(UNKNOWN, |&(name, _)| name)
+        .to_string()
+}
+
+fn resolution_order(value: &str) -> usize {
+    RESOLUTION_BUCKETS
+        .iter()
+        .position(|&(name, _)| name == value)
+        .unwrap_or(RESOLUTION_BUCKETS.len())
+}
+
+fn codec_bucket(video: &VideoItem) -> String {
+    video
+        .codec
+        .as_ref()
+        .map_or_else(|| UNKNOWN.to_string(), |codec| codec.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{FilterContext, FilterEngine};
+    use crate::types::FilterCriteria;
+
+    fn create_test_video(id: &str, folder: &str, tags: &[&str], rating: Option<u8>) -> VideoItem {
+        VideoItem {
+            id: id.to_string(),
+            name: format!("video_{}", id),
+            path: format!("/path/{}/{}.mp4", folder, id),
+            folder: Some(folder.to_string()),
+            size: 1024,
+            height: Some(1080),
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            rating,
+            ..Default::default()
+        }
+    }
+
+    fn videos() -> Vec<VideoItem> {
+        vec![
+            create_test_video("1", "a", &["cats", "funny"], Some(5)),
+            create_test_video("2", "a", &["cats"], Some(3)),
+            create_test_video("3", "b", &["dogs", "funny"], None),
+            create_test_video("4", "b", &["dogs"], Some(5)),
+        ]
+    }
+
+    fn facet<'f>(values: &'f [FacetValue], value: &str) -> &'f FacetValue {
+        values.iter().find(|v| v.value == value).unwrap()
+    }
+
+    #[test]
+    fn test_folder_facet_toggles() {
+        let videos = videos();
+        let mut engine = FilterEngine::new();
+        engine.rebuild_index(&videos);
+
+        let criteria = FilterCriteria {
+            folder: Some("a".to_string()),
+            ..Default::default()
+        };
+        let evaluator = engine.evaluator(&criteria, FilterContext::default());
+        let (filtered, facets) = filter_with_facets(&evaluator, &videos);
+
+        assert_eq!(filtered, vec![0, 1]);
+        assert_eq!(facets.total, 2);
+
+        // Deselecting "a" shows everything, adding "b" shows both folders
+        let a = facet(&facets.folders, "a");
+        assert_eq!((a.count, a.toggled), (2, 4));
+        let b = facet(&facets.folders, "b");
+        assert_eq!((b.count, b.toggled), (0, 4));
+
+        // Ratings ignore their own (inactive) rule
+        assert_eq!(facet(&facets.ratings, "5").count, 1);
+        assert_eq!(facets.ratings[0].value, "5");
+        assert_eq!(facet(&facets.resolutions, "1080p").count, 2);
+    }
+
+    #[test]
+    fn test_facets_relax_only_their_own_rule() {
+        let videos = videos();
+        let mut engine = FilterEngine::new();
+        engine.rebuild_index(&videos);
+
+        let criteria = FilterCriteria {
+            folder: Some("a".to_string()),
+            min_rating: Some(4),
+            ..Default::default()
+        };
+        let evaluator = engine.evaluator(&criteria, FilterContext::default());
+        let (filtered, facets) = filter_with_facets(&evaluator, &videos);
+
+        assert_eq!(filtered, engine.apply_filters(&videos, &criteria, &FilterContext::default()));
+        assert_eq!(filtered, vec![0]);
+
+        // Ratings within folder "a"
+        let five = facet(&facets.ratings, "5");
+        assert_eq!((five.count, five.toggled), (1, 1));
+        let three = facet(&facets.ratings, "3");
+        assert_eq!((three.count, three.toggled), (0, 1));
+
+        // Folders among videos rated 4 or more
+        let a = facet(&facets.folders, "a");
+        assert_eq!((a.count, a.toggled), (1, 2));
+        let b = facet(&facets.folders, "b");
+        assert_eq!((b.count, b.toggled), (0, 2));
+    }
+
+    #[test]
+    fn test_folder_facet_derives_directories() {
+        let mut videos = videos();
+        let paths = ["/lib/x/1.mp4", "/lib/x/2.mp4", "/lib/y/3.mp4"];
+        for (video, path) in videos.iter_mut().zip(paths) {
+            video.folder = None;
+            video.path = path.to_string();
+        }
+        let mut engine = FilterEngine::new();
+        engine.rebuild_index(&videos);
+
+        let criteria = FilterCriteria::default();
+        let evaluator = engine.evaluator(&criteria, FilterContext::default());
+        let (_, facets) = filter_with_facets(&evaluator, &videos);
+
+        // Folder-less videos are bucketed by the directory of their path
+        let x = facet(&facets.folders, "/lib/x");
+        assert_eq!((x.count, x.toggled), (2, 2));
+        let y = facet(&facets.folders, "/lib/y");
+        assert_eq!((y.count, y.toggled), (1, 1));
+        assert_eq!(facet(&facets.folders, "b").count, 1);
+    }
+
+    #[test]
+    fn test_tag_facet_toggles() {
+        let videos = videos();
+        let mut engine = FilterEngine::new();
+        engine.rebuild_index(&videos);
+
+        // AND mode
+        let criteria = FilterCriteria {
+            tags_all: vec!["funny".to_string()],
+            ..Default::default()
+        };
+        let evaluator = engine.evaluator(&criteria, FilterContext::default());
+        let (_, facets) = filter_with_facets(&evaluator, &videos);
+
+        assert_eq!(facets.total, 2);
+        let funny = facet(&facets.tags, "funny");
+        assert_eq!((funny.count, funny.toggled), (2, 4));
+        let cats = facet(&facets.tags, "cats");
+        assert_eq!((cats.count, cats.toggled), (1, 1));
+
+        // OR mode
+        let criteria = FilterCriteria {
+            tags_any: vec!["cats".to_string()],
+            ..Default::default()
+        };
+        let evaluator = engine.evaluator(&criteria, FilterContext::default());
+        let (_, facets) = filter_with_facets(&evaluator, &videos);
+
+        assert_eq!(facets.total, 2);
+        assert_eq!(facet(&facets.tags, "cats").toggled, 4);
+        assert_eq!(facet(&facets.tags, "dogs").toggled, 4);
+        assert_eq!(facet(&facets.tags, "funny").toggled, 3);
+    }
+}
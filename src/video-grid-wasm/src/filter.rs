@@ -1,94 +1,621 @@
-use crate::types::{FilterCriteria, VideoItem};
-use ahash::AHashSet;
+use crate::bitset::BitSet;
+use crate::query::{Expr, Predicate};
+use crate::search::SearchScores;
+use crate::types::{FilterCriteria, FilterRule, MissingPolicy, RangeFilter, VideoItem};
+use ahash::AHashMap;
+
+/// Per-call filter inputs computed outside the per-video checks
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FilterContext<'a> {
+    /// Search scores; videos without a score are filtered out
+    pub search: Option<&'a SearchScores>,
+    /// Compiled query expression
+    pub query: Option<&'a Expr>,
+    /// Reference time for relative date windows (ms since epoch)
+    pub now: Option<u64>,
+}
 
 /// High-performance filtering engine
+///
+/// Favorite and hidden flags are read from the videos themselves; the
+/// engine only keeps indexes derived from them, keyed by the dense video
+/// index (position in the collection). Flags are bitsets; folders and tags
+/// are sorted index lists, turned into bitsets for a filter pass so the
+/// indexed rules reduce to bitwise AND/OR/ANDNOT.
 pub struct FilterEngine {
-    favorite_ids: AHashSet<String>,
-    hidden_ids: AHashSet<String>,
+    /// Number of videos the indexes cover
+    len: usize,
+    favorites: BitSet,
+    hidden: BitSet,
+    /// Videos sharing a folder, which all get the same folder rule outcome
+    folder_groups: Vec<FolderGroup>,
+    folder_group_ids: AHashMap<FolderKey, usize>,
+    /// Inverted tag index: tag -> sorted indices of videos carrying it
+    tag_index: AHashMap<String, Vec<u32>>,
 }
 
+/// Videos with the same `folder` (or, without one, the same directory)
+struct FolderGroup {
+    /// Any member, used to evaluate the folder rule for the group
+    sample: usize,
+    members: Vec<u32>,
+}
+
+/// Scanner folder (`true`) or directory derived from the path (`false`)
+type FolderKey = (bool, String);
+
 impl FilterEngine {
     pub fn new() -> Self {
         Self {
-            favorite_ids: AHashSet::new(),
-            hidden_ids: AHashSet::new(),
+            len: 0,
+            favorites: BitSet::default(),
+            hidden: BitSet::default(),
+            folder_groups: Vec::new(),
+            folder_group_ids: AHashMap::new(),
+            tag_index: AHashMap::new(),
+        }
+    }
+
+    /// Rebuild every index for a video collection
+    pub fn rebuild_index(&mut self, videos: &[VideoItem]) {
+        *self = Self::new();
+        for (idx, video) in videos.iter().enumerate() {
+            self.add_video(idx, video);
+        }
+    }
+
+    /// Index a video appended to the collection at `idx`
+    pub fn add_video(&mut self, idx: usize, video: &VideoItem) {
+        self.len = self.len.max(idx + 1);
+        self.favorites.resize(self.len);
+        self.hidden.resize(self.len);
+        self.update_flags(idx, video);
+        self.add_to_folder_group(idx, video);
+        self.add_tags(idx, &video.tags);
+    }
+
+    /// Re-index a video changed in place
+    pub fn update_video(&mut self, idx: usize, before: &VideoItem, after: &VideoItem) {
+        self.update_flags(idx, after);
+        if folder_key(before) != folder_key(after) {
+            self.remove_from_folder_group(idx, before);
+            self.add_to_folder_group(idx, after);
+        }
+        if before.tags != after.tags {
+            self.remove_tags(idx, &before.tags);
+            self.add_tags(idx, &after.tags);
+        }
+    }
+
+    /// Sync the flag bitsets with one video's favorite and hidden flags
+    pub fn update_flags(&mut self, idx: usize, video: &VideoItem) {
+        self.favorites.set(idx, video.is_favorite);
+        self.hidden.set(idx, video.is_hidden);
+    }
+
+    fn add_to_folder_group(&mut self, idx: usize, video: &VideoItem) {
+        let group = match self.folder_group_ids.get(&folder_key(video)) {
+            Some(&group) => group,
+            None => {
+                self.folder_groups.push(FolderGroup {
+                    sample: idx,
+                    members: Vec::new(),
+                });
+                let group = self.folder_groups.len() - 1;
+                self.folder_group_ids.insert(folder_key(video), group);
+                group
+            }
+        };
+
+        let group = &mut self.folder_groups[group];
+        if group.members.is_empty() {
+            group.sample = idx;
+        }
+        insert_sorted(&mut group.members, idx);
+    }
+
+    fn remove_from_folder_group(&mut self, idx: usize, video: &VideoItem) {
+        if let Some(&group) = self.folder_group_ids.get(&folder_key(video)) {
+            let group = &mut self.folder_groups[group];
+            remove_sorted(&mut group.members, idx);
+            if let Some(&first) = group.members.first() {
+                group.sample = first as usize;
+            }
+        }
+    }
+
+    fn add_tags(&mut self, idx: usize, tags: &[String]) {
+        for tag in tags {
+            insert_sorted(self.tag_index.entry(tag.clone()).or_default(), idx);
+        }
+    }
+
+    fn remove_tags(&mut self, idx: usize, tags: &[String]) {
+        for tag in tags {
+            if let Some(ids) = self.tag_index.get_mut(tag) {
+                remove_sorted(ids, idx);
+                if ids.is_empty() {
+                    self.tag_index.remove(tag);
+                }
+            }
         }
     }
 
-    pub fn update_favorites(&mut self, ids: Vec<String>) {
-        self.favorite_ids.clear();
-        self.favorite_ids.extend(ids);
+    #[inline]
+    pub fn has_tag(&self, idx: usize, tag: &str) -> bool {
+        self.tag_index
+            .get(tag)
+            .is_some_and(|ids| ids.binary_search(&(idx as u32)).is_ok())
+    }
+
+    /// Videos carrying a tag
+    fn tag_set(&self, tag: &str) -> BitSet {
+        match self.tag_index.get(tag) {
+            Some(ids) => BitSet::from_indices(self.len, ids),
+            None => BitSet::new(self.len),
+        }
     }
 
-    pub fn update_hidden(&mut self, ids: Vec<String>) {
-        self.hidden_ids.clear();
-        self.hidden_ids.extend(ids);
+    /// Resolve criteria and context for evaluating videos one by one
+    pub fn evaluator<'a>(
+        &'a self,
+        criteria: &'a FilterCriteria,
+        context: FilterContext<'a>,
+    ) -> Evaluator<'a> {
+        Evaluator {
+            engine: self,
+            criteria,
+            context,
+            selected_folders: criteria
+                .folder
+                .iter()
+                .chain(&criteria.folders)
+                .map(String::as_str)
+                .collect(),
+            modified_range: criteria.modified_range(context.now),
+        }
     }
 
     /// Apply filters to video collection
-    /// Returns indices of videos that pass the filter
+    /// Returns indices of videos that pass the filter, search and query
+    ///
+    /// The indexed rules (folder, favorites, hidden, tags) are combined as
+    /// bitsets first; only the surviving videos are checked against the
+    /// remaining active rules.
     pub fn apply_filters(
         &self,
         videos: &[VideoItem],
         criteria: &FilterCriteria,
+        context: &FilterContext,
     ) -> Vec<usize> {
-        videos
+        let evaluator = self.evaluator(criteria, *context);
+        let residual = evaluator.residual_rules();
+
+        evaluator
+            .candidates(videos)
             .iter()
-            .enumerate()
-            .filter_map(|(idx, video)| {
-                if self.matches_criteria(video, criteria) {
-                    Some(idx)
-                } else {
-                    None
-                }
+            .filter(|&idx| {
+                residual
+                    .iter()
+                    .all(|&rule| evaluator.passes(rule, idx, &videos[idx]))
             })
             .collect()
     }
 
-    /// Fast filter check for a single video
-    #[inline]
-    fn matches_criteria(&self, video: &VideoItem, criteria: &FilterCriteria) -> bool {
-        // Folder filter
-        if let Some(ref folder) = criteria.folder {
-            if video.folder.as_ref() != Some(folder) {
-                return false;
-            }
-        }
-
-        // Favorites filter
-        if criteria.favorites_only && !self.favorite_ids.contains(&video.id) {
-            return false;
+    /// Evaluate a compiled query expression against a single video
+    pub fn matches_expr(&self, idx: usize, video: &VideoItem, expr: &Expr) -> bool {
+        match expr {
+            Expr::And(exprs) => exprs.iter().all(|expr| self.matches_expr(idx, video, expr)),
+            Expr::Or(exprs) => exprs.iter().any(|expr| self.matches_expr(idx, video, expr)),
+            Expr::Not(expr) => !self.matches_expr(idx, video, expr),
+            Expr::Term(predicate) => self.matches_predicate(idx, video, predicate),
         }
+    }
 
-        // Hidden filter
-        let is_hidden = self.hidden_ids.contains(&video.id);
-        if criteria.hidden_only {
-            // Show only hidden videos
-            if !is_hidden {
-                return false;
-            }
-        } else {
-            // Normal mode: hide hidden videos unless show_hidden is true
-            if is_hidden && !criteria.show_hidden {
-                return false;
+    #[inline]
+    fn matches_predicate(&self, idx: usize, video: &VideoItem, predicate: &Predicate) -> bool {
+        match predicate {
+            Predicate::Tag(tag) => self.has_tag(idx, tag),
+            Predicate::Folder(folder) => video_in_folder(video, folder, false),
+            Predicate::Name(text) => video.name.to_lowercase().contains(text.as_str()),
+            Predicate::Text(text) => {
+                video.name.to_lowercase().contains(text.as_str())
+                    || video
+                        .folder
+                        .as_ref()
+                        .is_some_and(|folder| folder.to_lowercase().contains(text.as_str()))
             }
+            Predicate::Codec(codec) => video
+                .codec
+                .as_ref()
+                .is_some_and(|c| c.eq_ignore_ascii_case(codec)),
+            Predicate::Favorite => video.is_favorite,
+            Predicate::Hidden => video.is_hidden,
+            Predicate::Rating(cmp) => video.rating.is_some_and(|r| cmp.matches(r as f64)),
+            Predicate::Duration(cmp) => video.duration.is_some_and(|d| cmp.matches(d)),
+            Predicate::Size(cmp) => cmp.matches(video.size as f64),
+            Predicate::Width(cmp) => video.width.is_some_and(|w| cmp.matches(w as f64)),
+            Predicate::Height(cmp) => video.height.is_some_and(|h| cmp.matches(h as f64)),
+            Predicate::Bitrate(cmp) => video.bitrate.is_some_and(|b| cmp.matches(b as f64)),
         }
-
-        true
     }
 
     /// Get videos that match the filter criteria
+    #[allow(dead_code)]
     pub fn filter_videos(
         &self,
         videos: &[VideoItem],
         criteria: &FilterCriteria,
     ) -> Vec<VideoItem> {
-        videos
+        self.apply_filters(videos, criteria, &FilterContext::default())
+            .into_iter()
+            .map(|idx| videos[idx].clone())
+            .collect()
+    }
+}
+
+/// Filter criteria and context resolved once per filter pass
+pub struct Evaluator<'a> {
+    engine: &'a FilterEngine,
+    criteria: &'a FilterCriteria,
+    context: FilterContext<'a>,
+    /// `folder` and `folders` combined
+    selected_folders: Vec<&'a str>,
+    modified_range: (u64, u64),
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn criteria(&self) -> &'a FilterCriteria {
+        self.criteria
+    }
+
+    pub fn selected_folders(&self) -> &[&'a str] {
+        &self.selected_folders
+    }
+
+    /// Check a video against every rule, stopping at the first failure
+    #[inline]
+    pub fn matches(&self, idx: usize, video: &VideoItem) -> bool {
+        FilterRule::ALL.iter().all(|&rule| self.passes(rule, idx, video))
+    }
+
+    /// Mask of `FilterRule::bit`s for every rule the video fails
+    pub fn rejected_rules(&self, idx: usize, video: &VideoItem) -> u32 {
+        FilterRule::ALL
             .iter()
-            .filter(|video| self.matches_criteria(video, criteria))
-            .cloned()
+            .filter(|&&rule| !self.passes(rule, idx, video))
+            .fold(0, |mask, rule| mask | rule.bit())
+    }
+
+    /// Check a single rule
+    pub fn passes(&self, rule: FilterRule, idx: usize, video: &VideoItem) -> bool {
+        let criteria = self.criteria;
+        let engine = self.engine;
+
+        match rule {
+            // Any selected folder, none of the excluded ones
+            FilterRule::Folder => self.matches_folders(video, &self.selected_folders),
+            FilterRule::Favorites => !criteria.favorites_only || video.is_favorite,
+            FilterRule::Hidden => {
+                if criteria.hidden_only {
+                    // Show only hidden videos
+                    video.is_hidden
+                } else {
+                    // Normal mode: hide hidden videos unless show_hidden is true
+                    !video.is_hidden || criteria.show_hidden
+                }
+            }
+            // All (AND), any (OR), exclude (NOT)
+            FilterRule::Tags => {
+                criteria.tags_all.iter().all(|tag| engine.has_tag(idx, tag))
+                    && (criteria.tags_any.is_empty()
+                        || criteria.tags_any.iter().any(|tag| engine.has_tag(idx, tag)))
+                    && !criteria.tags_exclude.iter().any(|tag| engine.has_tag(idx, tag))
+            }
+            FilterRule::Rating => {
+                let bounded = criteria.min_rating.is_some() || criteria.max_rating.is_some();
+                matches_optional(video.rating, criteria.unrated, bounded, |rating| {
+                    criteria.min_rating.is_none_or(|min| rating >= min)
+                        && criteria.max_rating.is_none_or(|max| rating <= max)
+                })
+            }
+            FilterRule::Duration => matches_range(&criteria.duration, video.duration),
+            FilterRule::Size => matches_range(&criteria.size, Some(video.size as f64)),
+            FilterRule::Width => matches_range(&criteria.width, video.width.map(f64::from)),
+            FilterRule::Height => matches_range(&criteria.height, video.height.map(f64::from)),
+            FilterRule::Bitrate => {
+                matches_range(&criteria.bitrate, video.bitrate.map(f64::from))
+            }
+            // Allow/deny lists
+            FilterRule::Codec => {
+                let bounded = !criteria.codecs.is_empty();
+                matches_optional(video.codec.as_deref(), criteria.unknown_codec, bounded, |codec| {
+                    (criteria.codecs.is_empty()
+                        || criteria.codecs.iter().any(|c| c.eq_ignore_ascii_case(codec)))
+                        && !criteria.exclude_codecs.iter().any(|c| c.eq_ignore_ascii_case(codec))
+                })
+            }
+            FilterRule::Date => {
+                let (min, max) = self.modified_range;
+                (min..=max).contains(&video.last_modified)
+            }
+            FilterRule::Search => self
+                .context
+                .search
+                .is_none_or(|scores| scores.contains_key(&idx)),
+            FilterRule::Query => self
+                .context
+                .query
+                .is_none_or(|expr| engine.matches_expr(idx, video, expr)),
+        }
+    }
+
+    /// Whether a rule can reject anything under these criteria
+    pub fn is_active(&self, rule: FilterRule) -> bool {
+        let criteria = self.criteria;
+        let range_active = |range: &RangeFilter| range.is_bounded() || range.missing.is_some();
+
+        match rule {
+            FilterRule::Folder => {
+                !self.selected_folders.is_empty() || !criteria.exclude_folders.is_empty()
+            }
+            FilterRule::Favorites => criteria.favorites_only,
+            FilterRule::Hidden => criteria.hidden_only || !criteria.show_hidden,
+            FilterRule::Tags => {
+                !criteria.tags_all.is_empty()
+                    || !criteria.tags_any.is_empty()
+                    || !criteria.tags_exclude.is_empty()
+            }
+            FilterRule::Rating => {
+                criteria.min_rating.is_some()
+                    || criteria.max_rating.is_some()
+                    || criteria.unrated.is_some()
+            }
+            FilterRule::Duration => range_active(&criteria.duration),
+            FilterRule::Size => range_active(&criteria.size),
+            FilterRule::Width => range_active(&criteria.width),
+            FilterRule::Height => range_active(&criteria.height),
+            FilterRule::Bitrate => range_active(&criteria.bitrate),
+            FilterRule::Codec => {
+                !criteria.codecs.is_empty()
+                    || !criteria.exclude_codecs.is_empty()
+                    || criteria.unknown_codec.is_some()
+            }
+            FilterRule::Date => self.modified_range != (0, u64::MAX),
+            FilterRule::Search => self.context.search.is_some(),
+            FilterRule::Query => self.context.query.is_some(),
+        }
+    }
+
+    /// Active rules the indexes don't answer, checked per candidate
+    pub fn residual_rules(&self) -> Vec<FilterRule> {
+        FilterRule::ALL
+            .into_iter()
+            .filter(|&rule| !rule.is_indexed() && self.is_active(rule))
             .collect()
     }
+
+    /// Videos passing every indexed rule, combined as bitsets
+    pub fn candidates(&self, videos: &[VideoItem]) -> BitSet {
+        self.candidates_relaxing(videos, 0)
+    }
+
+    /// `candidates`, ignoring the indexed rules in the `relaxed` mask
+    pub fn candidates_relaxing(&self, videos: &[VideoItem], relaxed: u32) -> BitSet {
+        let criteria = self.criteria;
+        let engine = self.engine;
+        let applies = |rule: FilterRule| relaxed & rule.bit() == 0 && self.is_active(rule);
+        let mut result = BitSet::full(engine.len);
+
+        if applies(FilterRule::Favorites) {
+            result.intersect_with(&engine.favorites);
+        }
+
+        if applies(FilterRule::Hidden) {
+            if criteria.hidden_only {
+                result.intersect_with(&engine.hidden);
+            } else {
+                result.difference_with(&engine.hidden);
+            }
+        }
+
+        if applies(FilterRule::Folder) {
+            for group in &engine.folder_groups {
+                if !self.matches_folders(&videos[group.sample], &self.selected_folders) {
+                    for &idx in &group.members {
+                        result.remove(idx as usize);
+                    }
+                }
+            }
+        }
+
+        if !applies(FilterRule::Tags) {
+            return result;
+        }
+        for tag in &criteria.tags_all {
+            result.intersect_with(&engine.tag_set(tag));
+        }
+        if !criteria.tags_any.is_empty() {
+            let mut any = BitSet::new(engine.len);
+            for tag in &criteria.tags_any {
+                any.union_with(&engine.tag_set(tag));
+            }
+            result.intersect_with(&any);
+        }
+        for tag in &criteria.tags_exclude {
+            result.difference_with(&engine.tag_set(tag));
+        }
+
+        result
+    }
+
+    /// Human-readable reason why a video fails a rule
+    pub fn rejection_reason(&self, rule: FilterRule, video: &VideoItem) -> String {
+        let criteria = self.criteria;
+        let folder = video.folder.as_deref().unwrap_or("");
+
+        match rule {
+            FilterRule::Folder => {
+                if self.matches_folders(video, &[]) {
+                    format!("folder '{}' is not one of {:?}", folder, self.selected_folders)
+                } else {
+                    format!("folder '{}' is excluded", folder)
+                }
+            }
+            FilterRule::Favorites => "not a favorite".to_string(),
+            FilterRule::Hidden => {
+                if criteria.hidden_only {
+                    "not hidden, showing hidden videos only".to_string()
+                } else {
+                    "video is hidden".to_string()
+                }
+            }
+            FilterRule::Tags => format!("tags {:?} do not satisfy the tag filter", video.tags),
+            FilterRule::Rating => match video.rating {
+                Some(rating) => format!("rating {} is outside the rating filter", rating),
+                None => "video is unrated".to_string(),
+            },
+            FilterRule::Duration => describe_range("duration", video.duration),
+            FilterRule::Size => describe_range("size", Some(video.size as f64)),
+            FilterRule::Width => describe_range("width", video.width.map(f64::from)),
+            FilterRule::Height => describe_range("height", video.height.map(f64::from)),
+            FilterRule::Bitrate => describe_range("bitrate", video.bitrate.map(f64::from)),
+            FilterRule::Codec => match video.codec {
+                Some(ref codec) => format!("codec '{}' is not allowed", codec),
+                None => "codec is unknown".to_string(),
+            },
+            FilterRule::Date => format!(
+                "last modified {} is outside the date range",
+                video.last_modified
+            ),
+            FilterRule::Search => "does not match the search".to_string(),
+            FilterRule::Query => "does not match the query".to_string(),
+        }
+    }
+
+    /// Folder rule against an arbitrary selection (used to probe toggles)
+    pub fn matches_folders(&self, video: &VideoItem, selected: &[&str]) -> bool {
+        let recursive = self.criteria.include_subfolders;
+
+        (selected.is_empty()
+            || selected.iter().any(|folder| video_in_folder(video, folder, recursive)))
+            && !self
+                .criteria
+                .exclude_folders
+                .iter()
+                .any(|folder| video_in_folder(video, folder, recursive))
+    }
+}
+
+/// Check an optional value against a filter, applying the missing-value
+/// policy when the value is `None`. Without an explicit policy, unknown
+/// values only pass when the filter is not bounded.
+#[inline]
+fn matches_optional<T>(
+    value: Option<T>,
+    policy: Option<MissingPolicy>,
+    bounded: bool,
+    in_range: impl FnOnce(T) -> bool,
+) -> bool {
+    match (value, policy) {
+        (None, Some(MissingPolicy::Exclude)) => false,
+        (None, Some(_)) => true,
+        (None, None) => !bounded,
+        (Some(_), Some(MissingPolicy::Only)) => false,
+        (Some(value), _) => in_range(value),
+    }
+}
+
+/// Check whether a video lives in a folder (or its subtree when recursive).
+///
+/// Uses the scanner-provided relative `folder` when present; otherwise the
+/// folder is derived from `path`, where it may be any trailing run of
+/// directory components. `/` and `\` are treated as the same separator.
+pub fn video_in_folder(video: &VideoItem, folder: &str, recursive: bool) -> bool {
+    if let Some(ref video_folder) = video.folder {
+        return folder_matches(video_folder, folder, recursive);
+    }
+
+    let dir = parent_dir(&video.path);
+
+    // Try every component boundary of the directory
+    std::iter::once(0)
+        .chain(dir.match_indices(is_separator).map(|(i, _)| i + 1))
+        .any(|start| folder_matches(&dir[start..], folder, recursive))
+}
+
+/// Insert into a sorted index list, ignoring duplicates
+fn insert_sorted(ids: &mut Vec<u32>, idx: usize) {
+    if let Err(position) = ids.binary_search(&(idx as u32)) {
+        ids.insert(position, idx as u32);
+    }
+}
+
+fn remove_sorted(ids: &mut Vec<u32>, idx: usize) {
+    if let Ok(position) = ids.binary_search(&(idx as u32)) {
+        ids.remove(position);
+    }
+}
+
+fn folder_key(video: &VideoItem) -> FolderKey {
+    let (scanned, folder) = folder_of(video);
+    (scanned, folder.to_string())
+}
+
+/// Folder a video is grouped under: its scanner-provided `folder` (`true`),
+/// or the directory of its path when it has none (`false`)
+pub fn folder_of(video: &VideoItem) -> (bool, &str) {
+    match video.folder {
+        Some(ref folder) => (true, folder),
+        None => (false, parent_dir(&video.path)),
+    }
+}
+
+/// Directory part of a path (empty for a bare file name)
+fn parent_dir(path: &str) -> &str {
+    match path.rfind(is_separator) {
+        Some(end) => &path[..end],
+        None => "",
+    }
+}
+
+#[inline]
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+/// Compare a directory against a selected folder, ignoring separator style
+/// and trailing separators. The empty folder is the library root.
+fn folder_matches(dir: &str, folder: &str, recursive: bool) -> bool {
+    let dir = dir.trim_end_matches(is_separator);
+    let folder = folder.trim_end_matches(is_separator);
+
+    if folder.is_empty() {
+        return recursive || dir.is_empty();
+    }
+    if dir.len() < folder.len() || !dir.is_char_boundary(folder.len()) {
+        return false;
+    }
+
+    let (head, rest) = dir.split_at(folder.len());
+    let same_head = head
+        .chars()
+        .zip(folder.chars())
+        .all(|(a, b)| a == b || (is_separator(a) && is_separator(b)));
+
+    same_head && (rest.is_empty() || (recursive && rest.starts_with(is_separator)))
+}
+
+fn describe_range(field: &str, value: Option<f64>) -> String {
+    match value {
+        Some(value) => format!("{} {} is outside the range", field, value),
+        None => format!("{} is unknown", field),
+    }
+}
+
+#[inline]
+fn matches_range(range: &RangeFilter, value: Option<f64>) -> bool {
+    matches_optional(value, range.missing, range.is_bounded(), |value| range.contains(value))
 }
 
 impl Default for FilterEngine {
@@ -108,15 +635,9 @@ mod tests {
             path: format!("/path/{}.mp4", id),
             folder: folder.map(|s| s.to_string()),
             size: 1024,
-            last_modified: 0,
-            duration: None,
-            width: None,
-            height: None,
-            resolution: None,
-            codec: None,
-            bitrate: None,
             is_favorite: is_fav,
-            is_hidden: is_hidden,
+            is_hidden,
+            ..Default::default()
         }
     }
 
@@ -128,6 +649,7 @@ mod tests {
             create_test_video("2", Some("folder2"), false, false),
             create_test_video("3", Some("folder1"), false, false),
         ];
+        engine.rebuild_index(&videos);
 
         let criteria = FilterCriteria {
             folder: Some("folder1".to_string()),
@@ -143,13 +665,13 @@ mod tests {
     #[test]
     fn test_favorites_filter() {
         let mut engine = FilterEngine::new();
-        engine.update_favorites(vec!["1".to_string(), "3".to_string()]);
 
         let videos = vec![
             create_test_video("1", None, true, false),
             create_test_video("2", None, false, false),
             create_test_video("3", None, true, false),
         ];
+        engine.rebuild_index(&videos);
 
         let criteria = FilterCriteria {
             favorites_only: true,
@@ -163,13 +685,13 @@ mod tests {
     #[test]
     fn test_hidden_filter() {
         let mut engine = FilterEngine::new();
-        engine.update_hidden(vec!["2".to_string()]);
 
         let videos = vec![
             create_test_video("1", None, false, false),
             create_test_video("2", None, false, true),
             create_test_video("3", None, false, false),
         ];
+        engine.rebuild_index(&videos);
 
         // Normal mode: hide hidden videos
         let criteria = FilterCriteria {
@@ -188,4 +710,366 @@ mod tests {
         assert_eq!(filtered.len(), 1);
         assert_eq!(filtered[0].id, "2");
     }
+
+    #[test]
+    fn test_tag_filter() {
+        let mut engine = FilterEngine::new();
+
+        let mut videos = vec![
+            create_test_video("1", None, false, false),
+            create_test_video("2", None, false, false),
+            create_test_video("3", None, false, false),
+        ];
+        videos[0].tags = vec!["cats".to_string(), "funny".to_string()];
+        videos[1].tags = vec!["cats".to_string()];
+        videos[2].tags = vec!["dogs".to_string(), "funny".to_string()];
+        engine.rebuild_index(&videos);
+
+        // AND: must have both tags
+        let criteria = FilterCriteria {
+            tags_all: vec!["cats".to_string(), "funny".to_string()],
+            ..Default::default()
+        };
+        let filtered = engine.filter_videos(&videos, &criteria);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id, "1");
+
+        // OR: either tag
+        let criteria = FilterCriteria {
+            tags_any: vec!["cats".to_string(), "dogs".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(engine.filter_videos(&videos, &criteria).len(), 3);
+
+        // NOT combined with OR
+        let criteria = FilterCriteria {
+            tags_any: vec!["funny".to_string()],
+            tags_exclude: vec!["dogs".to_string()],
+            ..Default::default()
+        };
+        let filtered = engine.filter_videos(&videos, &criteria);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id, "1");
+    }
+
+    #[test]
+    fn test_rating_filter() {
+        let mut engine = FilterEngine::new();
+
+        let mut videos = vec![
+            create_test_video("1", None, false, false),
+            create_test_video("2", None, false, false),
+            create_test_video("3", None, false, false),
+        ];
+        videos[0].rating = Some(5);
+        videos[1].rating = Some(3);
+        engine.rebuild_index(&videos);
+
+        // 4 stars and up excludes unrated by default
+        let criteria = FilterCriteria {
+            min_rating: Some(4),
+            ..Default::default()
+        };
+        let filtered = engine.filter_videos(&videos, &criteria);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id, "1");
+
+        // Explicitly include unrated
+        let criteria = FilterCriteria {
+            max_rating: Some(3),
+            unrated: Some(MissingPolicy::Include),
+            ..Default::default()
+        };
+        assert_eq!(engine.filter_videos(&videos, &criteria).len(), 2);
+
+        // Only unrated
+        let criteria = FilterCriteria {
+            unrated: Some(MissingPolicy::Only),
+            ..Default::default()
+        };
+        let filtered = engine.filter_videos(&videos, &criteria);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id, "3");
+    }
+
+    #[test]
+    fn test_query_filter() {
+        let mut engine = FilterEngine::new();
+
+        let mut videos = vec![
+            create_test_video("1", Some("Trips"), false, false),
+            create_test_video("2", Some("Trips"), false, false),
+            create_test_video("3", Some("Trips"), false, true),
+        ];
+        for video in &mut videos {
+            video.tags = vec!["cats".to_string()];
+            video.rating = Some(5);
+        }
+        videos[0].duration = Some(30.0);
+        videos[1].duration = Some(90.0);
+        videos[2].duration = Some(10.0);
+        engine.rebuild_index(&videos);
+
+        let expr = crate::query::parse(r#"tag:cats rating:>=4 folder:"Trips" -hidden dur:<60s"#)
+            .unwrap();
+        let criteria = FilterCriteria {
+            show_hidden: true,
+            ..Default::default()
+        };
+        let context = FilterContext {
+            query: Some(&expr),
+            ..Default::default()
+        };
+
+        assert_eq!(engine.apply_filters(&videos, &criteria, &context), vec![0]);
+    }
+
+    #[test]
+    fn test_metadata_range_filter() {
+        let mut engine = FilterEngine::new();
+
+        let mut videos = vec![
+            create_test_video("1", None, false, false),
+            create_test_video("2", None, false, false),
+            create_test_video("3", None, false, false),
+            create_test_video("4", None, false, false),
+        ];
+        // 4K HEVC, 90s
+        videos[0].height = Some(2160);
+        videos[0].codec = Some("HEVC".to_string());
+        videos[0].duration = Some(90.0);
+        // 4K HEVC, 5 minutes
+        videos[1].height = Some(2160);
+        videos[1].codec = Some("hevc".to_string());
+        videos[1].duration = Some(300.0);
+        // 1080p H.264, 60s
+        videos[2].height = Some(1080);
+        videos[2].codec = Some("h264".to_string());
+        videos[2].duration = Some(60.0);
+        // Metadata not extracted yet
+
+        engine.rebuild_index(&videos);
+
+        // All 4K HEVC clips under two minutes
+        let criteria = FilterCriteria {
+            height: RangeFilter { min: Some(2160.0), ..Default::default() },
+            duration: RangeFilter { max: Some(120.0), ..Default::default() },
+            codecs: vec!["hevc".to_string()],
+            ..Default::default()
+        };
+        let filtered = engine.filter_videos(&videos, &criteria);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id, "1");
+
+        // Unknown duration included on request
+        let criteria = FilterCriteria {
+            duration: RangeFilter {
+                max: Some(120.0),
+                missing: Some(MissingPolicy::Include),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(engine.filter_videos(&videos, &criteria).len(), 3);
+
+        // Only videos still missing metadata
+        let criteria = FilterCriteria {
+            duration: RangeFilter {
+                missing: Some(MissingPolicy::Only),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let filtered = engine.filter_videos(&videos, &criteria);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id, "4");
+
+        // Codec deny list keeps unknown codecs
+        let criteria = FilterCriteria {
+            exclude_codecs: vec!["HEVC".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(engine.filter_videos(&videos, &criteria).len(), 2);
+    }
+
+    #[test]
+    fn test_folder_subtree_filter() {
+        let mut engine = FilterEngine::new();
+
+        let mut videos = vec![
+            create_test_video("1", Some("Trips"), false, false),
+            create_test_video("2", Some("Trips/Paris"), false, false),
+            create_test_video("3", Some("Trips\\Rome"), false, false),
+            create_test_video("4", Some("Family"), false, false),
+            create_test_video("5", None, false, false),
+        ];
+        videos[4].path = "/library/Trips/Paris/night/clip.mp4".to_string();
+        engine.rebuild_index(&videos);
+
+        // Exact folder only (default)
+        let criteria = FilterCriteria {
+            folder: Some("Trips".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(engine.filter_videos(&videos, &criteria).len(), 1);
+
+        // Subtree, with the folder derived from the path when missing
+        let criteria = FilterCriteria {
+            folder: Some("Trips".to_string()),
+            include_subfolders: true,
+            ..Default::default()
+        };
+        assert_eq!(engine.filter_videos(&videos, &criteria).len(), 4);
+
+        // Multiple folders with an excluded subtree
+        let criteria = FilterCriteria {
+            folders: vec!["Trips".to_string(), "Family".to_string()],
+            include_subfolders: true,
+            exclude_folders: vec!["Trips/Paris".to_string()],
+            ..Default::default()
+        };
+        let ids: Vec<String> = engine
+            .filter_videos(&videos, &criteria)
+            .into_iter()
+            .map(|v| v.id)
+            .collect();
+        assert_eq!(ids, vec!["1", "3", "4"]);
+    }
+
+    #[test]
+    fn test_date_filter() {
+        const DAY: u64 = 24 * 60 * 60 * 1000;
+        let mut engine = FilterEngine::new();
+        let now = 100 * DAY;
+
+        let mut videos = vec![
+            create_test_video("1", None, false, false),
+            create_test_video("2", None, false, false),
+            create_test_video("3", None, false, false),
+        ];
+        videos[0].last_modified = now - DAY;
+        videos[1].last_modified = now - 10 * DAY;
+        videos[2].last_modified = now - 40 * DAY;
+        engine.rebuild_index(&videos);
+
+        // Last 7 days
+        let criteria = FilterCriteria {
+            modified_within: Some(7 * DAY),
+            ..Default::default()
+        };
+        let context = FilterContext {
+            now: Some(now),
+            ..Default::default()
+        };
+        assert_eq!(engine.apply_filters(&videos, &criteria, &context), vec![0]);
+
+        // Older than a week, but within an absolute range
+        let criteria = FilterCriteria {
+            modified_older_than: Some(7 * DAY),
+            modified_after: Some(now - 30 * DAY),
+            ..Default::default()
+        };
+        assert_eq!(engine.apply_filters(&videos, &criteria, &context), vec![1]);
+
+        // Absolute range only
+        let criteria = FilterCriteria {
+            modified_after: Some(now - 50 * DAY),
+            modified_before: Some(now - 5 * DAY),
+            ..Default::default()
+        };
+        assert_eq!(engine.filter_videos(&videos, &criteria).len(), 2);
+    }
+
+    /// Synthetic library with folders, tags, flags and ratings
+    fn library(count: usize) -> Vec<VideoItem> {
+        (0..count)
+            .map(|i| {
+                let mut video = create_test_video(
+                    &i.to_string(),
+                    Some(&format!("folder{}", i % 50)),
+                    i % 7 == 0,
+                    i % 11 == 0,
+                );
+                video.tags = (0..3).map(|t| format!("tag{}", (i * 31 + t * 17) % 200)).collect();
+                video.rating = Some((i % 6) as u8).filter(|&r| r > 0);
+                video
+            })
+            .collect()
+    }
+
+    fn library_criteria() -> FilterCriteria {
+        FilterCriteria {
+            folders: (0..25).map(|f| format!("folder{}", f)).collect(),
+            exclude_folders: vec!["folder3".to_string()],
+            tags_any: (0..40).map(|t| format!("tag{}", t)).collect(),
+            tags_exclude: vec!["tag7".to_string()],
+            min_rating: Some(2),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_indexed_filters_match_per_video_checks() {
+        let mut engine = FilterEngine::new();
+        let videos = library(1000);
+        engine.rebuild_index(&videos);
+
+        for criteria in [
+            library_criteria(),
+            FilterCriteria {
+                favorites_only: true,
+                tags_all: vec!["tag5".to_string()],
+                ..Default::default()
+            },
+            FilterCriteria {
+                hidden_only: true,
+                ..Default::default()
+            },
+        ] {
+            let evaluator = engine.evaluator(&criteria, FilterContext::default());
+            let expected: Vec<usize> = (0..videos.len())
+                .filter(|&idx| evaluator.matches(idx, &videos[idx]))
+                .collect();
+            let filtered = engine.apply_filters(&videos, &criteria, &FilterContext::default());
+            assert_eq!(filtered, expected);
+        }
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_apply_filters_100k() {
+        use std::time::Instant;
+
+        let mut engine = FilterEngine::new();
+        let videos = library(100_000);
+        engine.rebuild_index(&videos);
+        let criteria = library_criteria();
+        let runs = 20;
+
+        let start = Instant::now();
+        let mut per_video = Vec::new();
+        for _ in 0..runs {
+            let evaluator = engine.evaluator(&criteria, FilterContext::default());
+            per_video = (0..videos.len())
+                .filter(|&idx| evaluator.matches(idx, &videos[idx]))
+                .collect();
+        }
+        let per_video_time = start.elapsed() / runs;
+
+        let start = Instant::now();
+        let mut indexed = Vec::new();
+        for _ in 0..runs {
+            indexed = engine.apply_filters(&videos, &criteria, &FilterContext::default());
+        }
+        let indexed_time = start.elapsed() / runs;
+
+        assert_eq!(indexed, per_video);
+        println!(
+            "100k videos, {} pass: per-video {:?}, indexed {:?}",
+            indexed.len(),
+            per_video_time,
+            indexed_time
+        );
+    }
 }
@@ -0,0 +1,565 @@
+use crate::types::{
+    GridMetrics, HeaderGeometry, LayoutConfig, SortField, SortKey, TileGeometry, VideoItem,
+    Viewport,
+};
+use serde::Serialize;
+use std::ops::Range;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// How the filtered list is split into sections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionGrouping {
+    /// One section per folder
+    Folder,
+    /// One section per month of `last_modified` (UTC)
+    Month,
+}
+
+impl SectionGrouping {
+    /// Grouping for a sort's leading key. Sorting on that key keeps each
+    /// group in one contiguous run; other keys have no sections.
+    pub fn for_key(key: &SortKey) -> Option<Self> {
+        match key.field {
+            SortField::Folder => Some(SectionGrouping::Folder),
+            SortField::Date => Some(SectionGrouping::Month),
+            _ => None,
+        }
+    }
+
+    /// Section id of a video: its folder (empty without one), or its
+    /// month as `YYYY-MM`
+    fn section_id(self, video: &VideoItem) -> String {
+        match self {
+            SectionGrouping::Folder => video.folder.clone().unwrap_or_default(),
+            SectionGrouping::Month => {
+                let (year, month) = year_month(video.last_modified);
+                format!("{:04}-{:02}", year, month)
+            }
+        }
+    }
+}
+
+/// A run of consecutive filtered videos shown under one header
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Section {
+    pub id: String,
+    /// First position in the filtered list
+    pub start: usize,
+    /// Position after the last video
+    pub end: usize,
+    /// Top of the header (px)
+    pub top: f64,
+}
+
+/// How tiles are sized and broken into rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowSizing {
+    /// Fixed columns of equal tiles
+    Uniform { item_height: f64, items_per_row: usize },
+    /// Rows filling the container width, with tiles keeping their aspect
+    /// ratio and each row scaled to at most `row_height`
+    Justified { row_height: f64 },
+}
+
+impl RowSizing {
+    /// Height of a typical row, used to size the scroll buffer
+    fn nominal_row_height(&self) -> f64 {
+        match *self {
+            RowSizing::Uniform { item_height, .. } => item_height,
+            RowSizing::Justified { row_height } => row_height,
+        }
+    }
+}
+
+/// One row of tiles
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    /// Filtered positions of the tiles, `start..end`
+    pub start: usize,
+    pub end: usize,
+    /// Offset from the top of the grid (px)
+    pub top: f64,
+    pub height: f64,
+}
+
+/// Grid of rows stacked top to bottom, optionally split into sections
+/// that each start on a new row below a header of their own height.
+/// Rows and headers are `gap` apart, inside `padding` on every side.
+pub struct GridLayout {
+    sections: Vec<Section>,
+    rows: Vec<Row>,
+    header_height: f64,
+    sizing: RowSizing,
+    config: LayoutConfig,
+    height: f64,
+}
+
+/// Part of a layout inside a viewport
+#[derive(Debug, Clone, PartialEq)]
+pub struct VisibleRange {
+    /// Visible filtered positions, `start..end`
+    pub start: usize,
+    pub end: usize,
+    /// Indices of the visible rows
+    pub rows: Range<usize>,
+    /// Indices of the sections whose header is visible
+    pub headers: Range<usize>,
+}
+
+impl GridLayout {
+    /// Break the filtered videos into rows, and into sections when
+    /// grouped, and stack them top to bottom
+    pub fn build(
+        grouping: Option<SectionGrouping>,
+        videos: &[VideoItem],
+        filtered_indices: &[usize],
+        header_height: f64,
+        sizing: RowSizing,
+        config: LayoutConfig,
+    ) -> Self {
+        let mut layout = Self {
+            sections: Vec::new(),
+            rows: Vec::new(),
+            header_height,
+            sizing,
+            config,
+            height: 0.0,
+        };
+
+        let groups = match grouping {
+            Some(grouping) => group_runs(grouping, videos, filtered_indices),
+            None => vec![(None, 0..filtered_indices.len())],
+        };
+
+        let mut top = config.padding;
+        for (id, range) in groups {
+            if let Some(id) = id {
+                layout.sections.push(Section {
+                    id,
+                    start: range.start,
+                    end: range.end,
+                    top,
+                });
+                top += header_height + config.gap;
+            }
+            top = layout.add_rows(videos, filtered_indices, range, top);
+        }
+
+        // No gap after the last row or header
+        let blocks = layout.rows.len() + layout.sections.len();
+        if blocks > 0 {
+            top -= config.gap;
+        }
+        layout.height = top + config.padding;
+        layout
+    }
+
+    /// Whether the layout was built with these settings
+    pub fn fits(&self, header_height: f64, sizing: &RowSizing, config: &LayoutConfig) -> bool {
+        self.header_height == header_height && self.sizing == *sizing && self.config == *config
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+    #[cfg(test)]
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// Total content height (px)
+    pub fn height(&self) -> f64 {
+        self.height
+    }
+
+    /// Row holding a filtered position
+    pub fn row_of(&self, position: usize) -> Option<&Row> {
+        let row = self.rows.partition_point(|row| row.end <= position);
+        self.rows.get(row).filter(|row| row.start <= position)
+    }
+
+    /// First row reaching below `y`
+    pub fn row_at(&self, y: f64) -> Option<&Row> {
+        let row = self.rows.partition_point(|row| row.top + row.height <= y);
+        self.rows.get(row)
+    }
+
+    /// Whether tile widths are known, i.e. there is a container width
+    pub fn has_geometry(&self) -> bool {
+        self.config.container_width > 0.0
+    }
+
+    /// Videos and headers inside the viewport plus `buffer_rows` rows
+    /// above and below
+    pub fn visible_range(&self, viewport: &Viewport) -> VisibleRange {
+        let row_pitch = self.sizing.nominal_row_height() + self.config.gap;
+        let buffer = viewport.buffer_rows as f64 * row_pitch;
+        let top = viewport.scroll_top - buffer;
+        let bottom = viewport.scroll_top + viewport.viewport_height + buffer;
+
+        // Rows and headers are stacked in order, so the ones reaching
+        // into the viewport are found by binary search on their offsets
+        let first_row = self.rows.partition_point(|row| row.top + row.height <= top);
+        let end_row = self.rows.partition_point(|row| row.top < bottom).max(first_row);
+        let start = self
+            .rows
+            .get(first_row)
+            .map_or_else(|| self.rows.last().map_or(0, |row| row.end), |row| row.start);
+        let end = if end_row > first_row { self.rows[end_row - 1].end } else { start };
+
+        let first_header = self
+            .sections
+            .partition_point(|section| section.top + self.header_height <= top);
+        let end_header = self
+            .sections
+            .partition_point(|section| section.top < bottom)
+            .max(first_header);
+
+        VisibleRange {
+            start,
+            end,
+            rows: first_row..end_row,
+            headers: first_header..end_header,
+        }
+    }
+
+    /// Pixel positions of the tiles in a range of rows
+    pub fn tile_geometry(
+        &self,
+        videos: &[VideoItem],
+        filtered_indices: &[usize],
+        rows: Range<usize>,
+    ) -> Vec<TileGeometry> {
+        let mut tiles = Vec::new();
+        for row in &self.rows[rows] {
+            let mut x = self.config.padding;
+            for position in row.start..row.end {
+                let video = &videos[filtered_indices[position]];
+                let width = match self.sizing {
+                    RowSizing::Uniform { items_per_row, .. } => self.tile_width(items_per_row),
+                    RowSizing::Justified { .. } => aspect_ratio(video) * row.height,
+                };
+                tiles.push(TileGeometry {
+                    index: position,
+                    video_id: video.id.clone(),
+                    x,
+                    y: row.top,
+                    width,
+                    height: row.height,
+                });
+                x += width + self.config.gap;
+            }
+        }
+        tiles
+    }
+
+    /// Pixel positions of a range of section headers, spanning the
+    /// content width
+    pub fn header_geometry(&self, headers: Range<usize>) -> Vec<HeaderGeometry> {
+        self.sections[headers]
+            .iter()
+            .map(|section| HeaderGeometry {
+                section_id: section.id.clone(),
+                x: self.config.padding,
+                y: section.top,
+                width: self.config.content_width(),
+                height: self.header_height,
+            })
+            .collect()
+    }
+
+    /// Width of a tile in a row of `columns` equal tiles
+    fn tile_width(&self, columns: usize) -> f64 {
+        fill_width(&self.config, columns)
+    }
+
+    /// Append the rows of a run of filtered positions starting at `top`.
+    /// Returns the top of whatever comes next.
+    fn add_rows(
+        &mut self,
+        videos: &[VideoItem],
+        filtered_indices: &[usize],
+        range: Range<usize>,
+        mut top: f64,
+    ) -> f64 {
+        let gap = self.config.gap;
+        match self.sizing {
+            RowSizing::Uniform { item_height, items_per_row } => {
+                for start in range.clone().step_by(items_per_row.max(1)) {
+                    let end = (start + items_per_row.max(1)).min(range.end);
+                    self.rows.push(Row { start, end, top, height: item_height });
+                    top += item_height + gap;
+                }
+            }
+            RowSizing::Justified { row_height } => {
+                // Fill each row until its tiles at `row_height` span the
+                // content width, then scale the row down to fit exactly
+                let width = self.config.content_width();
+                let mut start = range.start;
+                let mut aspect_sum = 0.0;
+                for position in range.clone() {
+                    aspect_sum += aspect_ratio(&videos[filtered_indices[position]]);
+                    let gaps = (position - start) as f64 * gap;
+                    if aspect_sum * row_height + gaps >= width {
+                        let height = (width - gaps) / aspect_sum;
+                        self.rows.push(Row { start, end: position + 1, top, height });
+                        top += height + gap;
+                        start = position + 1;
+                        aspect_sum = 0.0;
+                    }
+                }
+                // A short last row keeps the target height
+                if start < range.end {
+                    self.rows.push(Row { start, end: range.end, top, height: row_height });
+                    top += row_height + gap;
+                }
+            }
+        }
+        top
+    }
+}
+
+/// Columns and tile size for a container: as many columns as fit tiles
+/// of the minimum width, with tiles stretched to fill the row up to the
+/// maximum width. `None` without a container or minimum tile width.
+pub fn grid_metrics(config: &LayoutConfig) -> Option<GridMetrics> {
+    let (min_width, _) = config.tile_width_range();
+    if config.container_width <= 0.0 || min_width <= 0.0 {
+        return None;
+    }
+
+    let content_width = config.content_width();
+    let columns = ((content_width + config.gap) / (min_width + config.gap)).floor() as usize;
+    let columns = columns.max(1);
+    let aspect_ratio = if config.aspect_ratio > 0.0 { config.aspect_ratio } else { 16.0 / 9.0 };
+    let tile_width = fill_width(config, columns);
+
+    Some(GridMetrics {
+        columns,
+        tile_width,
+        tile_height: tile_width / aspect_ratio,
+    })
+}
+
+/// Width of `columns` equal tiles filling the content width, capped at
+/// the maximum tile width
+fn fill_width(config: &LayoutConfig, columns: usize) -> f64 {
+    let columns = columns.max(1) as f64;
+    let width = ((config.content_width() - (columns - 1.0) * config.gap) / columns).max(0.0);
+    match config.tile_width_range() {
+        (_, max_width) if max_width > 0.0 => width.min(max_width),
+        _ => width,
+    }
+}
+
+/// Runs of consecutive filtered positions sharing a section id
+fn group_runs(
+    grouping: SectionGrouping,
+    videos: &[VideoItem],
+    filtered_indices: &[usize],
+) -> Vec<(Option<String>, Range<usize>)> {
+    let mut runs: Vec<(Option<String>, Range<usize>)> = Vec::new();
+    for (position, &idx) in filtered_indices.iter().enumerate() {
+        let id = grouping.section_id(&videos[idx]);
+        match runs.last_mut() {
+            Some((Some(last), range)) if *last == id => range.end = position + 1,
+            _ => runs.push((Some(id), position..position + 1)),
+        }
+    }
+    runs
+}
+
+/// Width over height of a video, 16:9 when its dimensions are unknown
+pub fn aspect_ratio(video: &VideoItem) -> f64 {
+    match (video.width, video.height) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => width as f64 / height as f64,
+        _ => 16.0 / 9.0,
+    }
+}
+
+/// UTC year and month (1-12) of a timestamp in ms since epoch
+fn year_month(ms: u64) -> (i64, u32) {
+    // Civil-from-days (Howard Hinnant), with eras of 400 years from March
+    let days = (ms / DAY_MS) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Density;
+
+    fn create_test_video(id: &str, folder: &str, timestamp: u64) -> VideoItem {
+        VideoItem {
+            id: id.to_string(),
+            name: format!("video_{}", id),
+            path: format!("/{}/{}.mp4", folder, id),
+            folder: Some(folder.to_string()),
+            size: 1024,
+            last_modified: timestamp,
+            ..Default::default()
+        }
+    }
+
+    const UNIFORM: RowSizing = RowSizing::Uniform { item_height: 100.0, items_per_row: 2 };
+
+    fn build(
+        folders: &[&str],
+        header_height: f64,
+        sizing: RowSizing,
+        config: LayoutConfig,
+    ) -> (Vec<VideoItem>, Vec<usize>, GridLayout) {
+        let videos: Vec<VideoItem> = folders
+            .iter()
+            .enumerate()
+            .map(|(i, folder)| create_test_video(&i.to_string(), folder, 0))
+            .collect();
+        let filtered: Vec<usize> = (0..videos.len()).collect();
+        let grouping = (header_height > 0.0).then_some(SectionGrouping::Folder);
+        let layout = GridLayout::build(grouping, &videos, &filtered, header_height, sizing, config);
+        (videos, filtered, layout)
+    }
+
+    fn viewport(scroll_top: f64, viewport_height: f64) -> Viewport {
+        Viewport {
+            scroll_top,
+            viewport_height,
+            item_height: 100.0,
+            items_per_row: 2,
+            buffer_rows: 0,
+        }
+    }
+
+    #[test]
+    fn test_year_month() {
+        assert_eq!(year_month(0), (1970, 1));
+        // 2024-02-29T23:59:59Z and 2024-03-01T00:00:00Z
+        assert_eq!(year_month(1_709_251_199_000), (2024, 2));
+        assert_eq!(year_month(1_709_251_200_000), (2024, 3));
+    }
+
+    #[test]
+    fn test_sections_start_on_new_rows() {
+        // Folder a: 3 videos (2 rows), b: 1 video, c: 4 videos (2 rows)
+        let folders = ["a", "a", "a", "b", "c", "c", "c", "c"];
+        let (_, _, layout) = build(&folders, 40.0, UNIFORM, LayoutConfig::default());
+
+        let tops: Vec<f64> = layout.sections().iter().map(|section| section.top).collect();
+        assert_eq!(tops, vec![0.0, 240.0, 380.0]);
+        assert_eq!(layout.height(), 620.0);
+
+        // Second row of a through the header of c
+        let range = layout.visible_range(&viewport(150.0, 250.0));
+        assert_eq!(range, VisibleRange { start: 2, end: 4, rows: 1..3, headers: 1..3 });
+
+        // Only the header of c
+        let range = layout.visible_range(&viewport(385.0, 30.0));
+        assert_eq!(range, VisibleRange { start: 4, end: 4, rows: 3..3, headers: 2..3 });
+
+        let range = layout.visible_range(&viewport(0.0, 1000.0));
+        assert_eq!(range, VisibleRange { start: 0, end: 8, rows: 0..5, headers: 0..3 });
+    }
+
+    #[test]
+    fn test_justified_rows() {
+        // 16:9 by default, 9:16 portrait, 2:1 wide
+        let sizes = [None, Some((1080, 1920)), Some((2000, 1000)), None, Some((1080, 1920))];
+        let videos: Vec<VideoItem> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| {
+                let mut video = create_test_video(&i.to_string(), "a", 0);
+                video.width = size.map(|(width, _)| width);
+                video.height = size.map(|(_, height)| height);
+                video
+            })
+            .collect();
+        let filtered: Vec<usize> = (0..videos.len()).collect();
+        let sizing = RowSizing::Justified { row_height: 200.0 };
+        let config = LayoutConfig { container_width: 800.0, ..Default::default() };
+        let layout = GridLayout::build(None, &videos, &filtered, 0.0, sizing, config);
+
+        // 16:9 + 9:16 + 2:1 = 4.34 wide at 200px overflows 800px and is
+        // scaled down; the rest is a short last row at full height
+        let rows = layout.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!((rows[0].start, rows[0].end), (0, 3));
+        assert!((rows[0].height - 800.0 / (16.0 / 9.0 + 0.5625 + 2.0)).abs() < 1e-9);
+        assert_eq!((rows[1].start, rows[1].end, rows[1].height), (3, 5, 200.0));
+        assert_eq!(rows[1].top, rows[0].height);
+        assert_eq!(layout.height(), rows[0].height + 200.0);
+
+        let range = layout.visible_range(&viewport(rows[0].height + 1.0, 10.0));
+        assert_eq!(range, VisibleRange { start: 3, end: 5, rows: 1..2, headers: 0..0 });
+    }
+
+    #[test]
+    fn test_geometry_with_gap_and_padding() {
+        let config = LayoutConfig {
+            container_width: 420.0,
+            gap: 20.0,
+            padding: 10.0,
+            ..Default::default()
+        };
+        let (videos, filtered, layout) = build(&["a", "a", "b"], 30.0, UNIFORM, config);
+
+        // Header a, a row of two, header b, a row of one
+        assert_eq!(layout.height(), 10.0 + 30.0 + 20.0 + 100.0 + 20.0 + 30.0 + 20.0 + 100.0 + 10.0);
+        let range = layout.visible_range(&viewport(0.0, 1000.0));
+
+        let rects: Vec<(f64, f64, f64, f64)> = layout
+            .tile_geometry(&videos, &filtered, range.rows)
+            .iter()
+            .map(|tile| (tile.x, tile.y, tile.width, tile.height))
+            .collect();
+        assert_eq!(
+            rects,
+            vec![
+                (10.0, 60.0, 190.0, 100.0),
+                (220.0, 60.0, 190.0, 100.0),
+                (10.0, 230.0, 190.0, 100.0),
+            ]
+        );
+
+        let headers = layout.header_geometry(range.headers);
+        assert_eq!(headers[1].section_id, "b");
+        assert_eq!((headers[1].x, headers[1].y, headers[1].width), (10.0, 180.0, 400.0));
+    }
+
+    #[test]
+    fn test_grid_metrics() {
+        let mut config = LayoutConfig {
+            container_width: 1000.0,
+            gap: 10.0,
+            min_tile_width: 200.0,
+            ..Default::default()
+        };
+
+        // (1000 + 10) / (200 + 10) fits 4 columns of (1000 - 30) / 4
+        let metrics = grid_metrics(&config).unwrap();
+        assert_eq!((metrics.columns, metrics.tile_width), (4, 242.5));
+        assert_eq!(metrics.tile_height, 242.5 * 9.0 / 16.0);
+
+        config.max_tile_width = 240.0;
+        assert_eq!(grid_metrics(&config).unwrap().tile_width, 240.0);
+
+        // Density overrides the tile widths; narrow containers keep a column
+        config.density = Some(Density::Spacious);
+        config.container_width = 300.0;
+        let metrics = grid_metrics(&config).unwrap();
+        assert_eq!((metrics.columns, metrics.tile_width), (1, 300.0));
+
+        config.container_width = 0.0;
+        assert_eq!(grid_metrics(&config), None);
+    }
+}
@@ -1,25 +1,59 @@
+mod bitset;
+mod facets;
 mod filter;
+mod layout;
+mod query;
 mod reconcile;
+mod search;
 mod sort;
 mod state;
 mod types;
 
-use filter::FilterEngine;
+use bitset::BitSet;
+use filter::{FilterContext, FilterEngine};
+use layout::{GridLayout, RowSizing, SectionGrouping};
 use reconcile::DomReconciler;
+use search::{SearchIndex, SearchScores};
 use sort::SortEngine;
 use state::{VideoStateManager, VideoState};
-use types::{FilterCriteria, SortMode, VideoItem, Viewport};
+use types::{
+    FacetCounts, FilterChange, FilterCriteria, FilterDelta, FilterExplanation, FilterRejection,
+    FilterRule, GridMetrics, LayoutChange, LayoutConfig, ShuffleWeights, SortKey, SortMode,
+    VideoItem, VideoUpdate, Viewport,
+};
 
+use ahash::{AHashMap, AHashSet};
 use wasm_bindgen::prelude::*;
 use serde_wasm_bindgen::{from_value, to_value};
+use std::collections::HashMap;
 
 /// Main video grid engine - high-performance grid management
 #[wasm_bindgen]
 pub struct VideoGridEngine {
     videos: Vec<VideoItem>,
+    /// Video id -> index into `videos`
+    id_index: AHashMap<String, usize>,
+    /// Videos passing the last filter, always kept in sort order
     filtered_indices: Vec<usize>,
     filter_engine: FilterEngine,
+    search_index: SearchIndex,
+    /// Last applied filter, used to patch the result incrementally
+    last_filter: Option<PreparedFilter>,
+    /// Search scores of the last filter, kept while ordering by relevance
+    relevance: Option<SearchScores>,
     sort_engine: SortEngine,
+    /// Height of section headers, 0 for a grid without sections
+    header_height: f64,
+    /// Container width, gap and padding of the grid
+    layout_config: LayoutConfig,
+    /// Columns and tile size derived from `layout_config`
+    metrics: Option<GridMetrics>,
+    /// Justified rows instead of the uniform grid
+    justified: Option<RowSizing>,
+    /// Row layout of the filtered list, dropped when it changes
+    layout: Option<GridLayout>,
+    /// Video to keep in place across the next change
+    anchor: Option<ScrollAnchor>,
     reconciler: DomReconciler,
     state_manager: VideoStateManager,
 }
@@ -35,9 +69,19 @@ impl VideoGridEngine {
 
         Self {
             videos: Vec::new(),
+            id_index: AHashMap::new(),
             filtered_indices: Vec::new(),
             filter_engine: FilterEngine::new(),
+            search_index: SearchIndex::new(),
+            last_filter: None,
+            relevance: None,
             sort_engine: SortEngine::new(SortMode::Folder),
+            header_height: 0.0,
+            layout_config: LayoutConfig::default(),
+            metrics: None,
+            justified: None,
+            layout: None,
+            anchor: None,
             reconciler: DomReconciler::new(),
             state_manager: VideoStateManager::new(max_active_videos),
         }
@@ -48,62 +92,268 @@ impl VideoGridEngine {
     pub fn set_videos(&mut self, videos_js: JsValue) -> Result<(), JsValue> {
         let videos: Vec<VideoItem> = from_value(videos_js)?;
         self.videos = videos;
+        self.rebuild_id_index();
         self.filtered_indices = (0..self.videos.len()).collect();
+        self.filter_engine.rebuild_index(&self.videos);
+        self.search_index.rebuild(&self.videos);
+        self.last_filter = None;
+        self.relevance = None;
+        self.anchor = None;
+        self.sort_engine.invalidate();
+        self.sort_filtered();
         Ok(())
     }
 
-    /// Update favorites list
+    /// Update favorites list (replaces every video's favorite flag).
+    /// Returns the `FilterDelta` patching the filtered list.
     #[wasm_bindgen(js_name = updateFavorites)]
-    pub fn update_favorites(&mut self, favorites: Vec<String>) {
-        self.filter_engine.update_favorites(favorites);
+    pub fn update_favorites(&mut self, favorites: Vec<String>) -> Result<JsValue, JsValue> {
+        let delta = self.replace_flags(favorites, |video| &mut video.is_favorite);
+        delta_to_js(&delta)
     }
 
-    /// Update hidden files list
+    /// Update hidden files list (replaces every video's hidden flag).
+    /// Returns the `FilterDelta` patching the filtered list.
     #[wasm_bindgen(js_name = updateHidden)]
-    pub fn update_hidden(&mut self, hidden: Vec<String>) {
-        self.filter_engine.update_hidden(hidden);
+    pub fn update_hidden(&mut self, hidden: Vec<String>) -> Result<JsValue, JsValue> {
+        let delta = self.replace_flags(hidden, |video| &mut video.is_hidden);
+        delta_to_js(&delta)
     }
 
-    /// Apply filters and return filtered video count
+    /// Set one video's favorite flag.
+    /// Returns the `FilterDelta` patching the filtered list.
+    #[wasm_bindgen(js_name = setFavorite)]
+    pub fn set_favorite(&mut self, video_id: String, favorite: bool) -> Result<JsValue, JsValue> {
+        let delta = self.set_flag(&video_id, favorite, |video| &mut video.is_favorite);
+        delta_to_js(&delta)
+    }
+
+    /// Set one video's hidden flag.
+    /// Returns the `FilterDelta` patching the filtered list.
+    #[wasm_bindgen(js_name = setHidden)]
+    pub fn set_hidden(&mut self, video_id: String, hidden: bool) -> Result<JsValue, JsValue> {
+        let delta = self.set_flag(&video_id, hidden, |video| &mut video.is_hidden);
+        delta_to_js(&delta)
+    }
+
+    /// Insert a video, or update the video with the same id, at its
+    /// position under the active filter and sort. User data (tags, rating,
+    /// flags, last viewed) left out of `video` keeps its current value;
+    /// a rating or last viewed time of 0 clears it.
+    /// Returns the `FilterDelta` with the resulting grid positions.
+    /// Throws when the rating is above 5.
+    #[wasm_bindgen(js_name = upsertVideo)]
+    pub fn upsert_video(&mut self, video_js: JsValue) -> Result<JsValue, JsValue> {
+        let video: VideoUpdate = from_value(video_js)?;
+        check_rating(&video.id, video.rating).map_err(|e| JsValue::from_str(&e))?;
+        let delta = self.upsert(vec![video]);
+        delta_to_js(&delta)
+    }
+
+    /// `upsertVideo` for a batch of videos (e.g. one scan chunk)
+    #[wasm_bindgen(js_name = upsertVideos)]
+    pub fn upsert_videos(&mut self, videos_js: JsValue) -> Result<JsValue, JsValue> {
+        let videos: Vec<VideoUpdate> = from_value(videos_js)?;
+        videos
+            .iter()
+            .try_for_each(|video| check_rating(&video.id, video.rating))
+            .map_err(|e| JsValue::from_str(&e))?;
+        let delta = self.upsert(videos);
+        delta_to_js(&delta)
+    }
+
+    /// Update tags for all videos from a `{ [videoId]: string[] }` map.
+    /// Videos missing from the map end up with no tags.
+    /// Returns the `FilterDelta` patching the filtered list.
+    #[wasm_bindgen(js_name = updateTags)]
+    pub fn update_tags(&mut self, tags_js: JsValue) -> Result<JsValue, JsValue> {
+        let tags: HashMap<String, Vec<String>> = from_value(tags_js)?;
+        let delta = self.replace_tags(tags);
+        delta_to_js(&delta)
+    }
+
+    /// Update star ratings for all videos from a `{ [videoId]: number }` map.
+    /// Videos missing from the map, or rated 0, become unrated.
+    /// Returns the `FilterDelta` patching the filtered list.
+    /// Throws, changing nothing, when a rating is above 5.
+    #[wasm_bindgen(js_name = updateRatings)]
+    pub fn update_ratings(&mut self, ratings_js: JsValue) -> Result<JsValue, JsValue> {
+        let ratings: HashMap<String, u8> = from_value(ratings_js)?;
+        ratings
+            .iter()
+            .try_for_each(|(id, &rating)| check_rating(id, Some(rating)))
+            .map_err(|e| JsValue::from_str(&e))?;
+        let delta = self.replace_ratings(ratings);
+        delta_to_js(&delta)
+    }
+
+    /// Apply filters and return filtered video count.
+    /// Throws `{ position, message }` when the query fails to parse.
     #[wasm_bindgen(js_name = applyFilters)]
     pub fn apply_filters(&mut self, criteria_js: JsValue) -> Result<usize, JsValue> {
-        let criteria: FilterCriteria = from_value(criteria_js)?;
-        self.filtered_indices = self.filter_engine.apply_filters(&self.videos, &criteria);
+        self.run_filters(criteria_js, false)?;
         Ok(self.filtered_indices.len())
     }
 
+    /// Apply filters and return facet counts (folders, tags, ratings,
+    /// resolutions, codecs) computed in the same pass
+    #[wasm_bindgen(js_name = applyFiltersWithFacets)]
+    pub fn apply_filters_with_facets(&mut self, criteria_js: JsValue) -> Result<JsValue, JsValue> {
+        let facets = self.run_filters(criteria_js, true)?.unwrap_or_default();
+        to_value(&facets).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Explain which filter rules reject a video and where the sort mode,
+    /// or search relevance, would place it in the result if it passed
+    #[wasm_bindgen(js_name = explainVideo)]
+    pub fn explain_video(&self, video_id: String, criteria_js: JsValue) -> Result<JsValue, JsValue> {
+        let prepared = self.prepare_filter(criteria_js)?;
+        let explanation = self.explain(&video_id, &prepared);
+        to_value(&explanation).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Check a filter query without applying it.
+    /// Returns `null` when valid, otherwise `{ position, message }`.
+    #[wasm_bindgen(js_name = validateQuery)]
+    pub fn validate_query(&self, query: &str) -> JsValue {
+        match query::parse(query) {
+            Ok(_) => JsValue::NULL,
+            Err(error) => query_error_to_js(error),
+        }
+    }
+
     /// Set sort mode and re-sort
     #[wasm_bindgen(js_name = setSortMode)]
     pub fn set_sort_mode(&mut self, mode: String) -> Result<(), JsValue> {
-        let sort_mode = match mode.as_str() {
-            "folder" => SortMode::Folder,
-            "date" => SortMode::Date,
-            "shuffle" => SortMode::Shuffle,
-            _ => SortMode::None,
-        };
+        let sort_mode = parse_sort_mode(&mode).map_err(|e| JsValue::from_str(&e))?;
+
+        if sort_mode == SortMode::WeightedShuffle {
+            // Pin the clock as `setWeightedShuffle` does
+            self.sort_engine.resume_weighted_shuffle(|| js_sys::Date::now() as u64);
+        } else {
+            self.sort_engine.set_mode(sort_mode);
+        }
+        self.sort_filtered();
+
+        Ok(())
+    }
 
-        self.sort_engine.set_mode(sort_mode);
+    /// Sort by ordered keys, e.g. `[{ field: 'folder', order: 'ASC' },
+    /// { field: 'rating', order: 'DESC' }]`, and re-sort. Videos that tie
+    /// on every key are ordered by id. An empty list keeps collection order.
+    #[wasm_bindgen(js_name = setSort)]
+    pub fn set_sort(&mut self, keys_js: JsValue) -> Result<(), JsValue> {
+        let keys: Vec<SortKey> = from_value(keys_js)?;
+        self.sort_engine.set_keys(keys);
+        self.sort_filtered();
+        Ok(())
+    }
 
-        // Re-sort filtered indices
-        if !self.filtered_indices.is_empty() {
-            let mut filtered_videos: Vec<VideoItem> = self.filtered_indices
-                .iter()
-                .map(|&idx| self.videos[idx].clone())
-                .collect();
+    /// Set the shuffle seed; the same seed reproduces the same order.
+    /// Re-sorts when shuffling.
+    #[wasm_bindgen(js_name = setShuffleSeed)]
+    pub fn set_shuffle_seed(&mut self, seed: u32) {
+        self.sort_engine.set_shuffle_seed(seed as u64);
+        if self.sort_engine.mode() == SortMode::Shuffle {
+            self.sort_filtered();
+        }
+    }
 
-            self.sort_engine.sort_videos(&mut filtered_videos);
+    /// Get the current shuffle seed (to persist it across restarts)
+    #[wasm_bindgen(js_name = getShuffleSeed)]
+    pub fn get_shuffle_seed(&self) -> u32 {
+        self.sort_engine.shuffle_seed() as u32
+    }
 
-            // Update indices to match sorted order
-            self.filtered_indices = filtered_videos
-                .iter()
-                .filter_map(|v| self.videos.iter().position(|video| video.id == v.id))
-                .collect();
+    /// Re-shuffle with a fresh random seed and return the seed.
+    /// Keeps a weighted shuffle weighted, otherwise switches to shuffle.
+    #[wasm_bindgen(js_name = reshuffle)]
+    pub fn reshuffle(&mut self) -> u32 {
+        let seed = (js_sys::Math::random() * u32::MAX as f64) as u32;
+        self.sort_engine.set_shuffle_seed(seed as u64);
+        if self.sort_engine.mode() != SortMode::WeightedShuffle {
+            self.sort_engine.set_mode(SortMode::Shuffle);
         }
+        self.sort_filtered();
+        seed
+    }
 
+    /// Shuffle favoring highly rated, favorite and long unseen videos,
+    /// tuned by `ShuffleWeights`. Uses the shuffle seed.
+    #[wasm_bindgen(js_name = setWeightedShuffle)]
+    pub fn set_weighted_shuffle(&mut self, weights_js: JsValue) -> Result<(), JsValue> {
+        let mut weights: ShuffleWeights = from_value(weights_js)?;
+        // Pin the clock so the order does not drift between calls
+        weights.now = weights.now.or_else(|| Some(js_sys::Date::now() as u64));
+        self.sort_engine.set_weighted_shuffle(weights);
+        self.sort_filtered();
         Ok(())
     }
 
-    /// Calculate viewport and return reconciliation result
+    /// Record when a video was watched (ms since epoch).
+    /// Returns the `FilterDelta` for its move under a weighted shuffle.
+    #[wasm_bindgen(js_name = setLastViewed)]
+    pub fn set_last_viewed(
+        &mut self,
+        video_id: String,
+        timestamp: f64,
+    ) -> Result<JsValue, JsValue> {
+        let mut delta = FilterDelta::default();
+        if let Some(&idx) = self.id_index.get(&video_id) {
+            let viewed = Some(timestamp as u64);
+            self.patch_video(idx, |video| video.last_viewed = viewed, &mut delta);
+        }
+        delta.total = self.filtered_indices.len();
+        delta_to_js(&delta)
+    }
+
+    /// Load a hand-arranged order (video ids, e.g. as saved for a
+    /// collection) and sort by it. Videos not in it follow, newest first.
+    #[wasm_bindgen(js_name = setCustomOrder)]
+    pub fn set_custom_order(&mut self, ids: Vec<String>) {
+        self.sort_engine.set_custom_order(ids);
+        self.sort_engine.set_mode(SortMode::Custom);
+        self.sort_filtered();
+    }
+
+    /// Get the custom order to persist it, including videos not in the
+    /// saved order yet
+    #[wasm_bindgen(js_name = getCustomOrder)]
+    pub fn get_custom_order(&self) -> Vec<String> {
+        self.sort_engine.export_custom_order(&self.videos)
+    }
+
+    /// Move the video at a grid position to another (drag and drop).
+    /// Switches to the custom order, starting from the current one.
+    /// Returns the `FilterDelta` of the move.
+    #[wasm_bindgen(js_name = moveItem)]
+    pub fn move_item(&mut self, from: usize, to: usize) -> Result<JsValue, JsValue> {
+        let delta = self.move_videos(from, 1, to).map_err(|e| JsValue::from_str(&e))?;
+        delta_to_js(&delta)
+    }
+
+    /// Move `count` videos starting at grid position `start` so the first
+    /// lands at `to` (a position in the final list), like `moveItem`
+    #[wasm_bindgen(js_name = moveRange)]
+    pub fn move_range(
+        &mut self,
+        start: usize,
+        count: usize,
+        to: usize,
+    ) -> Result<JsValue, JsValue> {
+        let delta = self.move_videos(start, count, to).map_err(|e| JsValue::from_str(&e))?;
+        delta_to_js(&delta)
+    }
+
+    /// Get the keys of the current sort (the preset keys for named modes)
+    #[wasm_bindgen(js_name = getSort)]
+    pub fn get_sort(&self) -> Result<JsValue, JsValue> {
+        to_value(self.sort_engine.keys()).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Calculate viewport and return reconciliation result.
+    /// Columns and tile height derived by `setLayout` take precedence
+    /// over `item_height` and `items_per_row`.
     #[wasm_bindgen(js_name = calculateViewport)]
     pub fn calculate_viewport(
         &mut self,
@@ -113,6 +363,7 @@ impl VideoGridEngine {
         items_per_row: usize,
         buffer_rows: usize,
     ) -> Result<JsValue, JsValue> {
+        let (item_height, items_per_row) = self.tile_size(item_height, items_per_row);
         let viewport = Viewport {
             scroll_top,
             viewport_height,
@@ -121,11 +372,25 @@ impl VideoGridEngine {
             buffer_rows,
         };
 
-        let result = self.reconciler.reconcile(
-            &self.videos,
-            &self.filtered_indices,
-            &viewport,
-        );
+        let grouping = self.section_grouping();
+        let result = match self.row_sizing(grouping, item_height, items_per_row) {
+            Some(sizing) => {
+                let layout = self.take_layout(grouping, sizing);
+                let result = self.reconciler.reconcile_layout(
+                    &self.videos,
+                    &self.filtered_indices,
+                    &layout,
+                    &viewport,
+                );
+                self.layout = Some(layout);
+                result
+            }
+            None => self.reconciler.reconcile(
+                &self.videos,
+                &self.filtered_indices,
+                &viewport,
+            ),
+        };
 
         // Update state manager with visible videos
         let visible_ids: Vec<String> = self.reconciler.get_visible_ids().to_vec();
@@ -145,6 +410,98 @@ impl VideoGridEngine {
         to_value(&result).map_err(|e| JsValue::from_str(&e.to_string()))
     }
 
+    /// Show a header above each group of the sort's leading key: per
+    /// folder when sorting by folder, per month when sorting by date.
+    /// Each group starts on a new row. A height of 0 turns headers off.
+    #[wasm_bindgen(js_name = setSectionHeaders)]
+    pub fn set_section_headers(&mut self, header_height: f64) {
+        self.header_height = header_height.max(0.0);
+        self.layout = None;
+    }
+
+    /// Set the container width, gap, padding and tile sizing
+    /// (`LayoutConfig`). With a container width, `calculateViewport`
+    /// returns the pixel geometry of the visible tiles and headers.
+    /// Returns the `LayoutChange` with the derived columns and tile size.
+    #[wasm_bindgen(js_name = setLayout)]
+    pub fn set_layout(&mut self, config_js: JsValue) -> Result<JsValue, JsValue> {
+        let config: LayoutConfig = from_value(config_js)?;
+        let change = self.configure_layout(config);
+        to_value(&change).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Update the container width (e.g. from a `ResizeObserver`).
+    /// Returns the `LayoutChange`; without `full_render` the tiles keep
+    /// their rows and only need resizing.
+    #[wasm_bindgen(js_name = resizeLayout)]
+    pub fn resize_layout(&mut self, container_width: f64) -> Result<JsValue, JsValue> {
+        let config = LayoutConfig {
+            container_width,
+            ..self.layout_config
+        };
+        let change = self.configure_layout(config);
+        to_value(&change).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Lay tiles out in justified rows: each row fills the container
+    /// width (see `setLayout`) with tiles at their aspect ratio (from
+    /// `width`/`height`, 16:9 when unknown), scaled to at most
+    /// `row_height`. `calculateViewport` then ignores `item_height` and
+    /// `items_per_row`. A height of 0 returns to the uniform grid.
+    #[wasm_bindgen(js_name = setJustifiedRows)]
+    pub fn set_justified_rows(&mut self, row_height: f64) {
+        self.justified = (row_height > 0.0).then_some(RowSizing::Justified { row_height });
+        self.layout = None;
+    }
+
+    /// Get the sections of the last `calculateViewport` (empty without
+    /// section headers)
+    #[wasm_bindgen(js_name = getSections)]
+    pub fn get_sections(&self) -> Result<JsValue, JsValue> {
+        let sections = self.layout.as_ref().map_or(&[][..], |layout| layout.sections());
+        to_value(sections).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Remember the video to keep in place across a filter, sort or zoom
+    /// change: the focused video if it is in the grid, otherwise the
+    /// first one at the top of the viewport. Takes the same tile size as
+    /// `calculateViewport`. Returns the anchored video id.
+    #[wasm_bindgen(js_name = captureAnchor)]
+    pub fn capture_anchor(
+        &mut self,
+        scroll_top: f64,
+        item_height: f64,
+        items_per_row: usize,
+        focused_id: Option<String>,
+    ) -> Option<String> {
+        self.anchor = self.find_anchor(scroll_top, item_height, items_per_row, focused_id);
+        let anchor = self.anchor.as_ref()?;
+        Some(self.videos[anchor.order[anchor.position]].id.clone())
+    }
+
+    /// After a change, get the `scroll_top` that puts the anchored video
+    /// (or its nearest neighbour that is still in the grid) back at the
+    /// same offset on screen. Takes the new tile size; consumes the
+    /// anchor. `undefined` when there is nothing to anchor to.
+    #[wasm_bindgen(js_name = restoreAnchor)]
+    pub fn restore_anchor(
+        &mut self,
+        viewport_height: f64,
+        item_height: f64,
+        items_per_row: usize,
+    ) -> Option<f64> {
+        let anchor = self.anchor.take()?;
+        let survivor = self.surviving_neighbour(&anchor)?;
+        let position = self.locate(survivor)?;
+
+        let layout = self.anchor_layout(item_height, items_per_row);
+        let top = layout.row_of(position).map(|row| row.top);
+        let max_scroll = (layout.height() - viewport_height).max(0.0);
+        self.layout = Some(layout);
+
+        Some((top? - anchor.offset).clamp(0.0, max_scroll))
+    }
+
     /// Get videos to load based on current viewport
     #[wasm_bindgen(js_name = getVideosToLoad)]
     pub fn get_videos_to_load(&self) -> Vec<String> {
@@ -214,12 +571,675 @@ impl VideoGridEngine {
     #[wasm_bindgen(js_name = reset)]
     pub fn reset(&mut self) {
         self.videos.clear();
+        self.id_index.clear();
         self.filtered_indices.clear();
+        self.filter_engine.rebuild_index(&self.videos);
+        self.search_index.rebuild(&self.videos);
+        self.last_filter = None;
+        self.relevance = None;
+        self.sort_engine.invalidate();
+        self.layout = None;
+        self.anchor = None;
         self.reconciler.reset();
         self.state_manager.clear();
     }
 }
 
+impl VideoGridEngine {
+    /// Parse criteria, filter the collection and optionally count facets
+    fn run_filters(
+        &mut self,
+        criteria_js: JsValue,
+        with_facets: bool,
+    ) -> Result<Option<FacetCounts>, JsValue> {
+        let prepared = self.prepare_filter(criteria_js)?;
+        let context = prepared.context();
+
+        let facets = if with_facets {
+            let evaluator = self.filter_engine.evaluator(&prepared.criteria, context);
+            let (filtered, facets) = facets::filter_with_facets(&evaluator, &self.videos);
+            self.filtered_indices = filtered;
+            Some(facets)
+        } else {
+            self.filtered_indices =
+                self.filter_engine.apply_filters(&self.videos, &prepared.criteria, &context);
+            None
+        };
+
+        self.relevance = prepared
+            .search
+            .clone()
+            .filter(|_| prepared.criteria.sort_by_relevance);
+        self.last_filter = Some(prepared);
+        self.sort_filtered();
+
+        Ok(facets)
+    }
+
+    /// Order the filtered list by relevance while a search asks for it,
+    /// otherwise by the sort mode.
+    fn sort_filtered(&mut self) {
+        self.layout = None;
+        if self.relevance.is_some() {
+            self.sort_by_relevance();
+            return;
+        }
+
+        self.sort_engine.sort_subset(&self.videos, &mut self.filtered_indices);
+    }
+
+    /// Set one user flag and patch the filtered list
+    fn set_flag(
+        &mut self,
+        video_id: &str,
+        value: bool,
+        flag: impl Fn(&mut VideoItem) -> &mut bool,
+    ) -> FilterDelta {
+        let mut delta = FilterDelta::default();
+        if let Some(&idx) = self.id_index.get(video_id) {
+            if *flag(&mut self.videos[idx]) != value {
+                self.patch_video(idx, |video| *flag(video) = value, &mut delta);
+            }
+        }
+        delta.total = self.filtered_indices.len();
+        delta
+    }
+
+    /// Set a user flag on exactly the listed videos and patch the filtered
+    /// list for the videos whose flag changed
+    fn replace_flags(
+        &mut self,
+        ids: Vec<String>,
+        flag: impl Fn(&mut VideoItem) -> &mut bool,
+    ) -> FilterDelta {
+        let ids: AHashSet<String> = ids.into_iter().collect();
+        let mut delta = FilterDelta::default();
+        for idx in 0..self.videos.len() {
+            let value = ids.contains(&self.videos[idx].id);
+            if *flag(&mut self.videos[idx]) != value {
+                self.patch_video(idx, |video| *flag(video) = value, &mut delta);
+            }
+        }
+        delta.total = self.filtered_indices.len();
+        delta
+    }
+
+    fn replace_tags(&mut self, mut tags: HashMap<String, Vec<String>>) -> FilterDelta {
+        let mut delta = FilterDelta::default();
+        for idx in 0..self.videos.len() {
+            let video_tags = tags.remove(&self.videos[idx].id).unwrap_or_default();
+            if video_tags != self.videos[idx].tags {
+                self.patch_video(idx, |video| video.tags = video_tags, &mut delta);
+            }
+        }
+        delta.total = self.filtered_indices.len();
+        delta
+    }
+
+    /// Insert new videos and update existing ones (matched by id),
+    /// placing each under the active filter and sort
+    fn upsert(&mut self, videos: Vec<VideoUpdate>) -> FilterDelta {
+        let mut delta = FilterDelta::default();
+        let searching = self
+            .last_filter
+            .as_ref()
+            .is_some_and(|prepared| prepared.search.is_some());
+
+        // Videos the active search has to score again wait outside the
+        // filtered list, so the search re-runs once for the whole batch
+        let mut unscored = Vec::new();
+        let mut waiting = AHashSet::new();
+        for video in videos {
+            let (idx, old_position, search_changed) = match self.id_index.get(&video.id) {
+                Some(&idx) => {
+                    let old_position = self.locate(idx);
+                    (idx, old_position, self.update_video(idx, |old| video.apply(old)))
+                }
+                None => (self.insert_video(video.into_video()), None, true),
+            };
+
+            let stale = searching && (search_changed || waiting.contains(&idx));
+            if !stale {
+                self.place_video(idx, old_position, &mut delta);
+                continue;
+            }
+            if let Some(position) = old_position {
+                self.filtered_indices.remove(position);
+                delta.changes.push(FilterChange::Remove {
+                    position,
+                    video_id: self.videos[idx].id.clone(),
+                });
+            }
+            if waiting.insert(idx) {
+                unscored.push(idx);
+            }
+        }
+
+        if !unscored.is_empty() {
+            self.refresh_search();
+            for idx in unscored {
+                self.place_video(idx, None, &mut delta);
+            }
+        }
+        delta.total = self.filtered_indices.len();
+        delta
+    }
+
+    /// Append a video to the collection and its indexes. Its search score
+    /// is stale until `refresh_search`.
+    fn insert_video(&mut self, video: VideoItem) -> usize {
+        let idx = self.videos.len();
+        self.id_index.insert(video.id.clone(), idx);
+        self.videos.push(video);
+
+        let video = &self.videos[idx];
+        self.filter_engine.add_video(idx, video);
+        self.search_index.add_video(idx, video);
+        self.sort_engine.invalidate();
+        idx
+    }
+
+    /// Re-run the active search after the search index changed, so
+    /// added or renamed videos get a score
+    fn refresh_search(&mut self) {
+        let Some(prepared) = &mut self.last_filter else {
+            return;
+        };
+        if prepared.search.is_none() {
+            return;
+        }
+
+        let query = prepared.criteria.search.as_deref().unwrap_or_default();
+        prepared.search = Some(self.search_index.search(query));
+        if self.relevance.is_some() {
+            self.relevance = prepared.search.clone();
+        }
+    }
+
+    /// Score one changed video against the active search, leaving the
+    /// other videos' scores as they are
+    fn rescore_video(&mut self, idx: usize) {
+        let Some(prepared) = &mut self.last_filter else {
+            return;
+        };
+        let Some(scores) = &mut prepared.search else {
+            return;
+        };
+
+        let query = prepared.criteria.search.as_deref().unwrap_or_default();
+        let score = self.search_index.score_video(query, idx);
+        for scores in [Some(scores), self.relevance.as_mut()].into_iter().flatten() {
+            match score {
+                Some(score) => scores.insert(idx, score),
+                None => scores.remove(&idx),
+            };
+        }
+    }
+
+    fn replace_ratings(&mut self, mut ratings: HashMap<String, u8>) -> FilterDelta {
+        let mut delta = FilterDelta::default();
+        for idx in 0..self.videos.len() {
+            let rating = ratings
+                .remove(&self.videos[idx].id)
+                .filter(|&rating| rating > 0);
+            if self.videos[idx].rating != rating {
+                self.patch_video(idx, |video| video.rating = rating, &mut delta);
+            }
+        }
+        delta.total = self.filtered_indices.len();
+        delta
+    }
+
+    /// Apply a change to one video, then move it into, out of or within
+    /// the filtered list, recording each step in `delta`
+    fn patch_video(
+        &mut self,
+        idx: usize,
+        update: impl FnOnce(&mut VideoItem),
+        delta: &mut FilterDelta,
+    ) {
+        // Locate before updating, while the sort keys still match the list
+        let old_position = self.locate(idx);
+        if self.update_video(idx, update) {
+            self.rescore_video(idx);
+        }
+        self.place_video(idx, old_position, delta);
+    }
+
+    /// Apply a change to one video and its indexes. Returns whether its
+    /// searchable text changed, leaving the active search's scores stale.
+    fn update_video(&mut self, idx: usize, update: impl FnOnce(&mut VideoItem)) -> bool {
+        let before = self.videos[idx].clone();
+        update(&mut self.videos[idx]);
+
+        let after = &self.videos[idx];
+        self.filter_engine.update_video(idx, &before, after);
+        self.sort_engine.video_changed(&before, after);
+        let search_changed =
+            before.name != after.name || before.folder != after.folder || before.path != after.path;
+        if search_changed {
+            self.search_index.update_video(idx, &before, after);
+        }
+        search_changed
+    }
+
+    /// Move a video into, out of or within the filtered list after a
+    /// change, given where it was before
+    fn place_video(&mut self, idx: usize, old_position: Option<usize>, delta: &mut FilterDelta) {
+        self.layout = None;
+        let passes = match &self.last_filter {
+            Some(prepared) => self
+                .filter_engine
+                .evaluator(&prepared.criteria, prepared.context())
+                .matches(idx, &self.videos[idx]),
+            None => true,
+        };
+
+        if let Some(position) = old_position {
+            self.filtered_indices.remove(position);
+            if passes && self.insertion_point(idx) == position {
+                self.filtered_indices.insert(position, idx);
+                return;
+            }
+            delta.changes.push(FilterChange::Remove {
+                position,
+                video_id: self.videos[idx].id.clone(),
+            });
+        }
+
+        if passes {
+            let position = self.insertion_point(idx);
+            self.filtered_indices.insert(position, idx);
+            delta.changes.push(FilterChange::Insert {
+                position,
+                video_id: self.videos[idx].id.clone(),
+            });
+        }
+    }
+
+    /// Move a range of the filtered list and arrange the custom order to
+    /// match. Videos filtered out keep their place relative to the
+    /// visible ones: the moved videos go just before their new visible
+    /// successor.
+    fn move_videos(
+        &mut self,
+        start: usize,
+        count: usize,
+        to: usize,
+    ) -> Result<FilterDelta, String> {
+        let total = self.filtered_indices.len();
+        if count == 0 || start + count > total || to + count > total {
+            return Err(format!(
+                "Cannot move {} videos from {} to {} of {}",
+                count, start, to, total
+            ));
+        }
+        if self.relevance.is_some() {
+            return Err("Cannot reorder videos sorted by relevance".to_string());
+        }
+
+        let mut delta = FilterDelta { changes: Vec::new(), total };
+        self.sort_engine.pin_custom_order(&self.videos);
+        if start == to {
+            return Ok(delta);
+        }
+
+        let moved: Vec<usize> = self.filtered_indices.drain(start..start + count).collect();
+        let ids: Vec<String> = moved.iter().map(|&idx| self.videos[idx].id.clone()).collect();
+        let neighbour = |position: usize| {
+            let idx = *self.filtered_indices.get(position)?;
+            Some(self.videos[idx].id.as_str())
+        };
+        let next = neighbour(to);
+        let previous = to.checked_sub(1).and_then(neighbour);
+        self.sort_engine.move_custom(&ids, next, previous);
+
+        self.filtered_indices.splice(to..to, moved);
+        self.layout = None;
+        for video_id in &ids {
+            delta.changes.push(FilterChange::Remove {
+                position: start,
+                video_id: video_id.clone(),
+            });
+        }
+        for (offset, video_id) in ids.into_iter().enumerate() {
+            delta.changes.push(FilterChange::Insert { position: to + offset, video_id });
+        }
+        Ok(delta)
+    }
+
+    /// Position of a video in the filtered list, if present.
+    /// Binary search over the sort order.
+    fn locate(&self, idx: usize) -> Option<usize> {
+        let position = self.insertion_point(idx);
+        (self.filtered_indices.get(position) == Some(&idx)).then_some(position)
+    }
+
+    /// Position a video takes in the sorted filtered list
+    fn insertion_point(&self, idx: usize) -> usize {
+        self.filtered_indices.partition_point(|&other| {
+            self.compare_positions(self.relevance.as_ref(), other, idx).is_lt()
+        })
+    }
+
+    /// Order of two videos in the filtered list: by relevance when
+    /// scores are given, otherwise by the sort mode, then by index
+    fn compare_positions(
+        &self,
+        relevance: Option<&SearchScores>,
+        a: usize,
+        b: usize,
+    ) -> std::cmp::Ordering {
+        match relevance {
+            Some(scores) => {
+                let score = |idx| scores.get(&idx).copied().unwrap_or(0.0);
+                score(b).total_cmp(&score(a))
+            }
+            None => self.sort_engine.compare(&self.videos[a], &self.videos[b]),
+        }
+        .then(a.cmp(&b))
+    }
+
+    /// Apply new layout settings and derive the grid metrics
+    fn configure_layout(&mut self, config: LayoutConfig) -> LayoutChange {
+        let metrics = layout::grid_metrics(&config);
+        let columns = |metrics: Option<GridMetrics>| metrics.map(|metrics| metrics.columns);
+
+        // Justified rows break differently at another width
+        let rows_changed = columns(metrics) != columns(self.metrics)
+            || (self.justified.is_some()
+                && config.container_width != self.layout_config.container_width);
+
+        if config != self.layout_config {
+            self.layout = None;
+        }
+        self.layout_config = config;
+        self.metrics = metrics;
+
+        LayoutChange {
+            metrics,
+            full_render: rows_changed,
+        }
+    }
+
+    /// Anchor on the focused video, or the first at `scroll_top`
+    fn find_anchor(
+        &mut self,
+        scroll_top: f64,
+        item_height: f64,
+        items_per_row: usize,
+        focused_id: Option<String>,
+    ) -> Option<ScrollAnchor> {
+        let focused = focused_id
+            .and_then(|id| self.id_index.get(&id).copied())
+            .and_then(|idx| self.locate(idx));
+
+        let layout = self.anchor_layout(item_height, items_per_row);
+        let anchor = match focused {
+            Some(position) => layout.row_of(position).map(|row| (position, row.top)),
+            None => layout.row_at(scroll_top).map(|row| (row.start, row.top)),
+        };
+        self.layout = Some(layout);
+
+        let (position, top) = anchor?;
+        Some(ScrollAnchor {
+            offset: top - scroll_top,
+            order: self.filtered_indices.clone(),
+            position,
+        })
+    }
+
+    /// The anchored video if it is still in the filtered list, otherwise
+    /// the closest one to it in the old order (preferring the next)
+    fn surviving_neighbour(&self, anchor: &ScrollAnchor) -> Option<usize> {
+        let mut members = BitSet::new(self.videos.len());
+        for &idx in &self.filtered_indices {
+            members.insert(idx);
+        }
+
+        let position = anchor.position;
+        (0..anchor.order.len()).find_map(|distance| {
+            let after = anchor.order.get(position + distance);
+            let before = position.checked_sub(distance).map(|before| &anchor.order[before]);
+            after
+                .into_iter()
+                .chain(before)
+                .copied()
+                .find(|&idx| members.contains(idx))
+        })
+    }
+
+    /// Row layout to place an anchor in, for any grid
+    fn anchor_layout(&mut self, item_height: f64, items_per_row: usize) -> GridLayout {
+        let (item_height, items_per_row) = self.tile_size(item_height, items_per_row);
+        let grouping = self.section_grouping();
+        let sizing = self
+            .row_sizing(grouping, item_height, items_per_row)
+            .unwrap_or(RowSizing::Uniform { item_height, items_per_row });
+        self.take_layout(grouping, sizing)
+    }
+
+    /// Tile height and columns: derived by `setLayout`, or the caller's
+    fn tile_size(&self, item_height: f64, items_per_row: usize) -> (f64, usize) {
+        match self.metrics {
+            Some(metrics) => (metrics.tile_height, metrics.columns),
+            None => (item_height, items_per_row),
+        }
+    }
+
+    /// Row sizing when the grid needs a row layout (sections, justified
+    /// rows, tile geometry); `None` for the plain uniform grid, which is
+    /// computed directly
+    fn row_sizing(
+        &self,
+        grouping: Option<SectionGrouping>,
+        item_height: f64,
+        items_per_row: usize,
+    ) -> Option<RowSizing> {
+        let has_width = self.layout_config.container_width > 0.0;
+        self.justified.filter(|_| has_width).or_else(|| {
+            (grouping.is_some() || has_width)
+                .then_some(RowSizing::Uniform { item_height, items_per_row })
+        })
+    }
+
+    /// Take the cached row layout of the filtered list, rebuilding it if
+    /// the layout settings changed
+    fn take_layout(&mut self, grouping: Option<SectionGrouping>, sizing: RowSizing) -> GridLayout {
+        let header_height = if grouping.is_some() { self.header_height } else { 0.0 };
+        let config = self.layout_config;
+        match self.layout.take() {
+            Some(layout) if layout.fits(header_height, &sizing, &config) => layout,
+            _ => GridLayout::build(
+                grouping,
+                &self.videos,
+                &self.filtered_indices,
+                header_height,
+                sizing,
+                config,
+            ),
+        }
+    }
+
+    /// Grouping of the section headers, if shown: by the leading sort
+    /// key, none while ordering by relevance
+    fn section_grouping(&self) -> Option<SectionGrouping> {
+        if self.header_height <= 0.0 || self.relevance.is_some() {
+            return None;
+        }
+        self.sort_engine.leading_key().and_then(SectionGrouping::for_key)
+    }
+
+    /// Parse criteria and resolve the query, search and clock they need
+    fn prepare_filter(&self, criteria_js: JsValue) -> Result<PreparedFilter, JsValue> {
+        let mut criteria: FilterCriteria = from_value(criteria_js)?;
+
+        let query = match criteria.query.as_deref() {
+            Some(query) => Some(query::parse(query).map_err(query_error_to_js)?),
+            None => None,
+        };
+
+        // A query that tests the hidden flag decides visibility on its own
+        if query.as_ref().is_some_and(|expr| expr.mentions_hidden()) {
+            criteria.show_hidden = true;
+        }
+
+        let search = criteria
+            .search
+            .as_deref()
+            .filter(|query| !query.trim().is_empty())
+            .map(|query| self.search_index.search(query));
+
+        // Only consult the clock when a relative window needs it
+        let now = criteria
+            .now
+            .or_else(|| criteria.has_relative_dates().then(|| js_sys::Date::now() as u64));
+
+        Ok(PreparedFilter {
+            criteria,
+            query,
+            search,
+            now,
+        })
+    }
+
+    fn rebuild_id_index(&mut self) {
+        self.id_index = self
+            .videos
+            .iter()
+            .enumerate()
+            .map(|(idx, video)| (video.id.clone(), idx))
+            .collect();
+    }
+
+    /// Explain how a filter treats a video and where the sort would put it
+    fn explain(&self, video_id: &str, prepared: &PreparedFilter) -> FilterExplanation {
+        let Some(&target) = self.id_index.get(video_id) else {
+            return FilterExplanation {
+                video_id: video_id.to_string(),
+                found: false,
+                passed: false,
+                rejections: Vec::new(),
+                position: None,
+            };
+        };
+
+        let evaluator = self.filter_engine.evaluator(&prepared.criteria, prepared.context());
+        let video = &self.videos[target];
+        let rejected = evaluator.rejected_rules(target, video);
+
+        let rejections = FilterRule::ALL
+            .iter()
+            .filter(|rule| rejected & rule.bit() != 0)
+            .map(|&rule| FilterRejection {
+                rule,
+                reason: evaluator.rejection_reason(rule, video),
+            })
+            .collect();
+
+        // Count passing videos the result order would place first
+        let relevance = prepared
+            .search
+            .as_ref()
+            .filter(|_| prepared.criteria.sort_by_relevance);
+        let position = self
+            .videos
+            .iter()
+            .enumerate()
+            .filter(|&(idx, other)| {
+                idx != target
+                    && evaluator.matches(idx, other)
+                    && self.compare_positions(relevance, idx, target).is_lt()
+            })
+            .count();
+
+        FilterExplanation {
+            video_id: video_id.to_string(),
+            found: true,
+            passed: rejected == 0,
+            rejections,
+            position: Some(position),
+        }
+    }
+
+    /// Order filtered videos by search relevance, best match first
+    fn sort_by_relevance(&mut self) {
+        if let Some(scores) = &self.relevance {
+            // Unscored videos rank last, as in `compare_positions`
+            let score = |idx| scores.get(&idx).copied().unwrap_or(0.0);
+            self.filtered_indices
+                .sort_by(|&a, &b| score(b).total_cmp(&score(a)).then(a.cmp(&b)));
+        }
+    }
+}
+
+/// Filter criteria with their query, search and clock resolved
+struct PreparedFilter {
+    criteria: FilterCriteria,
+    query: Option<query::Expr>,
+    search: Option<SearchScores>,
+    now: Option<u64>,
+}
+
+impl PreparedFilter {
+    fn context(&self) -> FilterContext<'_> {
+        FilterContext {
+            search: self.search.as_ref(),
+            query: self.query.as_ref(),
+            now: self.now,
+        }
+    }
+}
+
+/// Video kept in place across a change, see `captureAnchor`
+struct ScrollAnchor {
+    /// Offset of the video's row from the top of the viewport (px)
+    offset: f64,
+    /// Filtered list when captured, to find surviving neighbours
+    order: Vec<usize>,
+    /// Position of the video in `order`
+    position: usize,
+}
+
+fn delta_to_js(delta: &FilterDelta) -> Result<JsValue, JsValue> {
+    to_value(delta).map_err(|e| JsValue::from_str(&e.to_string()))
+}
+
+/// Sort mode for a `setSortMode` name
+fn parse_sort_mode(mode: &str) -> Result<SortMode, String> {
+    Ok(match mode {
+        "folder" => SortMode::Folder,
+        "date" => SortMode::Date,
+        "name" => SortMode::Name,
+        "size" => SortMode::Size,
+        "duration" => SortMode::Duration,
+        "resolution" => SortMode::Resolution,
+        "bitrate" => SortMode::Bitrate,
+        "rating" => SortMode::Rating,
+        "shuffle" => SortMode::Shuffle,
+        "weighted_shuffle" => SortMode::WeightedShuffle,
+        "custom" => SortMode::Custom,
+        "none" => SortMode::None,
+        _ => return Err(format!("Unknown sort mode '{}'", mode)),
+    })
+}
+
+/// Reject star ratings above 5 (0 clears a rating)
+fn check_rating(video_id: &str, rating: Option<u8>) -> Result<(), String> {
+    match rating {
+        Some(rating) if rating > 5 => Err(format!(
+            "Rating {} for video '{}' is not between 0 and 5",
+            rating, video_id
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Convert a query parse error into a `{ position, message }` JS object
+fn query_error_to_js(error: query::QueryError) -> JsValue {
+    to_value(&error).unwrap_or_else(|_| JsValue::from_str(&error.message))
+}
+
 /// Utility function to log to console (for debugging)
 #[wasm_bindgen]
 extern "C" {
@@ -245,19 +1265,119 @@ export interface VideoItem {
     bitrate?: number;
     is_favorite: boolean;
     is_hidden: boolean;
+    tags?: string[];
+    rating?: number;
+    last_viewed?: number;
 }
 
+export interface VideoUpdate extends Omit<VideoItem, 'is_favorite' | 'is_hidden'> {
+    is_favorite?: boolean;
+    is_hidden?: boolean;
+}
+
+export type MissingPolicy = 'include' | 'exclude' | 'only';
+
 export interface FilterCriteria {
     folder?: string;
+    folders?: string[];
+    include_subfolders?: boolean;
+    exclude_folders?: string[];
     favorites_only: boolean;
     hidden_only: boolean;
     show_hidden: boolean;
+    tags_all?: string[];
+    tags_any?: string[];
+    tags_exclude?: string[];
+    min_rating?: number;
+    max_rating?: number;
+    unrated?: MissingPolicy;
+    search?: string;
+    sort_by_relevance?: boolean;
+    query?: string;
+    duration?: RangeFilter;
+    size?: RangeFilter;
+    width?: RangeFilter;
+    height?: RangeFilter;
+    bitrate?: RangeFilter;
+    codecs?: string[];
+    exclude_codecs?: string[];
+    unknown_codec?: MissingPolicy;
+    modified_after?: number;
+    modified_before?: number;
+    modified_within?: number;
+    modified_older_than?: number;
+    now?: number;
 }
 
-export interface DomOperation {
-    type: 'Add' | 'Remove' | 'Move' | 'Update';
+export interface RangeFilter {
+    min?: number;
+    max?: number;
+    missing?: MissingPolicy;
+}
+
+export interface FacetValue {
+    value: string;
+    count: number;
+    toggled: number;
+}
+
+export interface FacetCounts {
+    total: number;
+    folders: FacetValue[];
+    tags: FacetValue[];
+    ratings: FacetValue[];
+    resolutions: FacetValue[];
+    codecs: FacetValue[];
+}
+
+export type FilterRule =
+    | 'folder' | 'favorites' | 'hidden' | 'tags' | 'rating' | 'duration' | 'size'
+    | 'width' | 'height' | 'bitrate' | 'codec' | 'date' | 'search' | 'query';
+
+export interface FilterExplanation {
     video_id: string;
+    found: boolean;
+    passed: boolean;
+    rejections: { rule: FilterRule; reason: string }[];
+    position?: number;
+}
+
+export type FilterChange =
+    | { op: 'remove'; position: number; video_id: string }
+    | { op: 'insert'; position: number; video_id: string };
+
+export interface FilterDelta {
+    changes: FilterChange[];
+    total: number;
+}
+
+export interface ShuffleWeights {
+    rating?: number;
+    favorite?: number;
+    unseen?: number;
+    unseen_days?: number;
+    now?: number;
+}
+
+export type SortField =
+    | 'folder' | 'date' | 'name' | 'size' | 'duration' | 'resolution' | 'bitrate' | 'rating';
+
+export interface SortKey {
+    field: SortField;
+    order?: 'ASC' | 'DESC';
+}
+
+export interface QueryError {
+    position: number;
+    message: string;
+}
+
+export interface DomOperation {
+    type: 'Add' | 'Remove' | 'Move' | 'Update' | 'AddHeader' | 'RemoveHeader';
+    video_id?: string;
+    section_id?: string;
     index?: number;
+    top?: number;
     from?: number;
     to?: number;
 }
@@ -267,6 +1387,54 @@ export interface ReconciliationResult {
     total_items: number;
     visible_start: number;
     visible_end: number;
+    content_height: number;
+    tiles: TileGeometry[];
+    headers: HeaderGeometry[];
+}
+
+export interface LayoutConfig {
+    container_width?: number;
+    gap?: number;
+    padding?: number;
+    min_tile_width?: number;
+    max_tile_width?: number;
+    aspect_ratio?: number;
+    density?: 'compact' | 'comfortable' | 'spacious';
+}
+
+export interface GridMetrics {
+    columns: number;
+    tile_width: number;
+    tile_height: number;
+}
+
+export interface LayoutChange {
+    metrics: GridMetrics | null;
+    full_render: boolean;
+}
+
+export interface TileGeometry {
+    index: number;
+    video_id: string;
+    x: number;
+    y: number;
+    width: number;
+    height: number;
+}
+
+export interface HeaderGeometry {
+    section_id: string;
+    x: number;
+    y: number;
+    width: number;
+    height: number;
+}
+
+export interface Section {
+    id: string;
+    start: number;
+    end: number;
+    top: number;
 }
 
 export interface GridStats {
@@ -277,3 +1445,564 @@ export interface GridStats {
     inViewport: number;
 }
 "#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_video(id: &str, folder: &str, timestamp: u64) -> VideoItem {
+        VideoItem {
+            id: id.to_string(),
+            name: format!("video_{}", id),
+            path: format!("/path/{}/{}.mp4", folder, id),
+            folder: Some(folder.to_string()),
+            size: 1024,
+            last_modified: timestamp,
+            ..Default::default()
+        }
+    }
+
+    fn create_engine(videos: Vec<VideoItem>) -> VideoGridEngine {
+        let mut engine = VideoGridEngine::new(10);
+        engine.videos = videos;
+        engine.rebuild_id_index();
+        engine.filtered_indices = (0..engine.videos.len()).collect();
+        engine.filter_engine.rebuild_index(&engine.videos);
+        engine.search_index.rebuild(&engine.videos);
+        engine
+    }
+
+    fn prepared(criteria: FilterCriteria) -> PreparedFilter {
+        PreparedFilter {
+            criteria,
+            query: None,
+            search: None,
+            now: None,
+        }
+    }
+
+    #[test]
+    fn test_explain_rejected_video() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 300),
+            create_test_video("2", "b", 200),
+            create_test_video("3", "a", 100),
+        ]);
+        engine.replace_flags(vec!["2".to_string()], |video| &mut video.is_hidden);
+        engine.sort_engine.set_mode(SortMode::Date);
+
+        let explanation = engine.explain(
+            "2",
+            &prepared(FilterCriteria {
+                folder: Some("a".to_string()),
+                ..Default::default()
+            }),
+        );
+
+        assert!(explanation.found);
+        assert!(!explanation.passed);
+        let rules: Vec<FilterRule> = explanation.rejections.iter().map(|r| r.rule).collect();
+        assert_eq!(rules, vec![FilterRule::Folder, FilterRule::Hidden]);
+
+        // Newest first: after video 1, before video 3
+        assert_eq!(explanation.position, Some(1));
+    }
+
+    #[test]
+    fn test_explain_unknown_video() {
+        let engine = create_engine(vec![create_test_video("1", "a", 0)]);
+
+        let explanation = engine.explain("missing", &prepared(FilterCriteria::default()));
+        assert!(!explanation.found);
+        assert_eq!(explanation.position, None);
+    }
+
+    #[test]
+    fn test_explain_position_by_relevance() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 300),
+            create_test_video("2", "a", 200),
+            create_test_video("3", "a", 100),
+        ]);
+        engine.videos[0].name = "beaches at dawn".to_string();
+        engine.videos[1].name = "beach".to_string();
+        engine.videos[2].name = "old beach".to_string();
+        engine.search_index.rebuild(&engine.videos);
+        engine.sort_engine.set_mode(SortMode::Date);
+
+        let mut filter = prepared(FilterCriteria {
+            search: Some("beach".to_string()),
+            sort_by_relevance: true,
+            ..Default::default()
+        });
+        filter.search = Some(engine.search_index.search("beach"));
+        let explanation = engine.explain("1", &filter);
+
+        // Same place the filtered grid puts it
+        engine.relevance = filter.search.clone();
+        engine.sort_filtered();
+        assert_eq!(explanation.position, engine.locate(0));
+
+        // Prefix match ranks after both exact matches, despite being newest
+        assert_eq!(explanation.position, Some(2));
+    }
+
+    #[test]
+    fn test_relevance_sort_with_other_rules() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 300),
+            create_test_video("2", "a", 200),
+            create_test_video("3", "a", 100),
+        ]);
+        engine.videos[0].name = "beaches at dawn".to_string();
+        engine.videos[1].name = "beach".to_string();
+        engine.videos[2].name = "forest".to_string();
+        engine.search_index.rebuild(&engine.videos);
+        favorite(&mut engine, "1", true);
+        favorite(&mut engine, "3", true);
+
+        apply(
+            &mut engine,
+            FilterCriteria {
+                search: Some("beach".to_string()),
+                sort_by_relevance: true,
+                favorites_only: true,
+                ..Default::default()
+            },
+        );
+        engine.relevance = engine.last_filter.as_ref().unwrap().search.clone();
+        engine.sort_filtered();
+        assert_eq!(engine.filtered_indices, vec![0]);
+
+        // Favoriting the exact match places it first
+        favorite(&mut engine, "2", true);
+        assert_eq!(engine.filtered_indices, vec![1, 0]);
+
+        // A listed video without a score sorts last instead of panicking
+        engine.filtered_indices.push(2);
+        engine.sort_filtered();
+        assert_eq!(engine.filtered_indices, vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_patch_rescores_renamed_video() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 300),
+            create_test_video("2", "a", 200),
+            create_test_video("3", "a", 100),
+        ]);
+        engine.videos[0].name = "beaches at dawn".to_string();
+        engine.videos[1].name = "beach".to_string();
+        engine.videos[2].name = "forest".to_string();
+        engine.search_index.rebuild(&engine.videos);
+        apply(
+            &mut engine,
+            FilterCriteria {
+                search: Some("beach".to_string()),
+                sort_by_relevance: true,
+                ..Default::default()
+            },
+        );
+        engine.relevance = engine.last_filter.as_ref().unwrap().search.clone();
+        engine.sort_filtered();
+        assert_eq!(engine.filtered_indices, vec![1, 0]);
+
+        // The renamed video gains a score and moves ahead by relevance;
+        // the one that stops matching drops out
+        let mut delta = FilterDelta::default();
+        engine.patch_video(2, |video| video.name = "beach".to_string(), &mut delta);
+        engine.patch_video(1, |video| video.name = "lake".to_string(), &mut delta);
+        assert_eq!(engine.filtered_indices, vec![2, 0]);
+
+        let expected = engine.search_index.search("beach");
+        assert_eq!(engine.last_filter.as_ref().unwrap().search.as_ref(), Some(&expected));
+        assert_eq!(engine.relevance.as_ref(), Some(&expected));
+    }
+
+    fn apply(engine: &mut VideoGridEngine, criteria: FilterCriteria) {
+        let mut prepared = prepared(criteria);
+        prepared.search = prepared
+            .criteria
+            .search
+            .as_deref()
+            .map(|query| engine.search_index.search(query));
+        engine.filtered_indices = engine.filter_engine.apply_filters(
+            &engine.videos,
+            &prepared.criteria,
+            &prepared.context(),
+        );
+        engine.last_filter = Some(prepared);
+        engine.sort_filtered();
+    }
+
+    fn favorite(engine: &mut VideoGridEngine, id: &str, value: bool) -> FilterDelta {
+        engine.set_flag(id, value, |video| &mut video.is_favorite)
+    }
+
+    /// What a scan reports for a video: its metadata, no user data
+    fn scanned(video: VideoItem) -> VideoUpdate {
+        VideoUpdate {
+            id: video.id,
+            name: video.name,
+            path: video.path,
+            folder: video.folder,
+            size: video.size,
+            last_modified: video.last_modified,
+            duration: video.duration,
+            width: video.width,
+            height: video.height,
+            resolution: video.resolution,
+            codec: video.codec,
+            bitrate: video.bitrate,
+            is_favorite: None,
+            is_hidden: None,
+            tags: None,
+            rating: None,
+            last_viewed: None,
+        }
+    }
+
+    #[test]
+    fn test_set_favorite_patches_result() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 300),
+            create_test_video("2", "a", 200),
+            create_test_video("3", "a", 100),
+        ]);
+        engine.sort_engine.set_mode(SortMode::Date);
+        engine.replace_flags(
+            vec!["1".to_string(), "3".to_string()],
+            |video| &mut video.is_favorite,
+        );
+        apply(
+            &mut engine,
+            FilterCriteria {
+                favorites_only: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(engine.filtered_indices, vec![0, 2]);
+
+        // Newly favorited video lands in sorted position
+        let delta = favorite(&mut engine, "2", true);
+        assert_eq!(
+            delta.changes,
+            vec![FilterChange::Insert { position: 1, video_id: "2".to_string() }]
+        );
+        assert_eq!(delta.total, 3);
+        assert_eq!(engine.filtered_indices, vec![0, 1, 2]);
+        assert!(engine.videos[1].is_favorite);
+
+        let delta = favorite(&mut engine, "1", false);
+        assert_eq!(
+            delta.changes,
+            vec![FilterChange::Remove { position: 0, video_id: "1".to_string() }]
+        );
+        assert_eq!(engine.filtered_indices, vec![1, 2]);
+        assert!(!engine.videos[0].is_favorite);
+
+        // No-op toggles report no change
+        assert!(favorite(&mut engine, "2", true).changes.is_empty());
+        assert!(favorite(&mut engine, "missing", true).changes.is_empty());
+    }
+
+    #[test]
+    fn test_update_ratings_delta() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 300),
+            create_test_video("2", "a", 200),
+            create_test_video("3", "a", 100),
+        ]);
+        engine.sort_engine.set_mode(SortMode::Date);
+        apply(
+            &mut engine,
+            FilterCriteria {
+                min_rating: Some(3),
+                ..Default::default()
+            },
+        );
+        assert!(engine.filtered_indices.is_empty());
+
+        let ratings = HashMap::from([("1".to_string(), 5), ("3".to_string(), 4)]);
+        let delta = engine.replace_ratings(ratings);
+        assert_eq!(
+            delta.changes,
+            vec![
+                FilterChange::Insert { position: 0, video_id: "1".to_string() },
+                FilterChange::Insert { position: 1, video_id: "3".to_string() },
+            ]
+        );
+
+        let ratings = HashMap::from([("3".to_string(), 4), ("2".to_string(), 3)]);
+        let delta = engine.replace_ratings(ratings);
+        assert_eq!(
+            delta.changes,
+            vec![
+                FilterChange::Remove { position: 0, video_id: "1".to_string() },
+                FilterChange::Insert { position: 0, video_id: "2".to_string() },
+            ]
+        );
+        assert_eq!(engine.filtered_indices, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_check_rating() {
+        assert_eq!(check_rating("1", None), Ok(()));
+        assert_eq!(check_rating("1", Some(0)), Ok(()));
+        assert_eq!(check_rating("1", Some(5)), Ok(()));
+        assert_eq!(
+            check_rating("1", Some(6)),
+            Err("Rating 6 for video '1' is not between 0 and 5".to_string())
+        );
+    }
+
+    #[test]
+    fn test_upsert_places_videos() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 300),
+            create_test_video("2", "b", 200),
+        ]);
+        engine.sort_engine.set_mode(SortMode::Date);
+        apply(
+            &mut engine,
+            FilterCriteria {
+                folders: vec!["a".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(engine.filtered_indices, vec![0]);
+
+        // New video in a selected folder lands by date; one elsewhere is
+        // added to the collection only
+        let delta = engine.upsert(vec![
+            scanned(create_test_video("3", "a", 400)),
+            scanned(create_test_video("4", "b", 100)),
+        ]);
+        assert_eq!(
+            delta.changes,
+            vec![FilterChange::Insert { position: 0, video_id: "3".to_string() }]
+        );
+        assert_eq!(engine.videos.len(), 4);
+        assert_eq!(engine.filtered_indices, vec![2, 0]);
+
+        // Updating a video moves it into the folder and down the list
+        let delta = engine.upsert(vec![scanned(create_test_video("2", "a", 100))]);
+        assert_eq!(
+            delta.changes,
+            vec![FilterChange::Insert { position: 2, video_id: "2".to_string() }]
+        );
+        let delta = engine.upsert(vec![scanned(create_test_video("3", "a", 50))]);
+        assert_eq!(
+            delta.changes,
+            vec![
+                FilterChange::Remove { position: 0, video_id: "3".to_string() },
+                FilterChange::Insert { position: 2, video_id: "3".to_string() },
+            ]
+        );
+        assert_eq!(engine.filtered_indices, vec![0, 1, 2]);
+        assert_eq!(engine.videos.len(), 4);
+    }
+
+    #[test]
+    fn test_upsert_batch_under_search() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 100),
+            create_test_video("2", "a", 200),
+        ]);
+        engine.videos[0].name = "beach".to_string();
+        engine.videos[1].name = "dunes".to_string();
+        engine.search_index.rebuild(&engine.videos);
+        engine.sort_engine.set_mode(SortMode::Date);
+        apply(
+            &mut engine,
+            FilterCriteria {
+                search: Some("beach".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(engine.filtered_indices, vec![0]);
+
+        // A new match and a rename into the search, scored in one pass
+        let mut added = scanned(create_test_video("3", "a", 300));
+        added.name = "beach party".to_string();
+        let mut renamed = scanned(create_test_video("2", "a", 200));
+        renamed.name = "beach dunes".to_string();
+        let delta = engine.upsert(vec![added, renamed]);
+
+        assert_eq!(
+            delta.changes,
+            vec![
+                FilterChange::Insert { position: 0, video_id: "3".to_string() },
+                FilterChange::Insert { position: 1, video_id: "2".to_string() },
+            ]
+        );
+        assert_eq!(engine.filtered_indices, vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn test_upsert_keeps_user_data() {
+        let mut engine = create_engine(vec![create_test_video("1", "a", 100)]);
+        engine.replace_tags(HashMap::from([("1".to_string(), vec!["cats".to_string()])]));
+        engine.replace_ratings(HashMap::from([("1".to_string(), 4)]));
+        favorite(&mut engine, "1", true);
+
+        // A rescan reports new metadata but no user data
+        let mut rescanned = create_test_video("1", "a", 200);
+        rescanned.name = "renamed".to_string();
+        engine.upsert(vec![scanned(rescanned)]);
+
+        let video = &engine.videos[0];
+        assert_eq!(video.name, "renamed");
+        assert_eq!(video.last_modified, 200);
+        assert_eq!(video.tags, vec!["cats".to_string()]);
+        assert_eq!(video.rating, Some(4));
+        assert!(video.is_favorite);
+
+        // User data the caller does send is applied
+        let mut update = scanned(create_test_video("1", "a", 200));
+        update.is_favorite = Some(false);
+        update.rating = Some(0);
+        update.last_viewed = Some(500);
+        engine.upsert(vec![update]);
+        assert!(!engine.videos[0].is_favorite);
+        assert_eq!(engine.videos[0].rating, None);
+        assert_eq!(engine.videos[0].last_viewed, Some(500));
+        assert_eq!(engine.videos[0].tags, vec!["cats".to_string()]);
+
+        // 0 clears the last viewed time, as it does the rating
+        let mut update = scanned(create_test_video("1", "a", 200));
+        update.last_viewed = Some(0);
+        engine.upsert(vec![update]);
+        assert_eq!(engine.videos[0].last_viewed, None);
+
+        let mut update = scanned(create_test_video("2", "a", 300));
+        update.last_viewed = Some(0);
+        engine.upsert(vec![update]);
+        assert_eq!(engine.videos[1].last_viewed, None);
+    }
+
+    #[test]
+    fn test_move_videos_arranges_custom_order() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 100),
+            create_test_video("2", "b", 200),
+            create_test_video("3", "a", 300),
+            create_test_video("4", "b", 400),
+            create_test_video("5", "a", 500),
+        ]);
+        engine.sort_engine.set_mode(SortMode::Date);
+        apply(
+            &mut engine,
+            FilterCriteria {
+                folders: vec!["a".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(engine.filtered_indices, vec![4, 2, 0]);
+
+        // Drag the first video to the end of the visible list
+        let delta = engine.move_videos(0, 1, 2).unwrap();
+        assert_eq!(
+            delta.changes,
+            vec![
+                FilterChange::Remove { position: 0, video_id: "5".to_string() },
+                FilterChange::Insert { position: 2, video_id: "5".to_string() },
+            ]
+        );
+        assert_eq!(engine.sort_engine.mode(), SortMode::Custom);
+        assert_eq!(engine.filtered_indices, vec![2, 0, 4]);
+        assert_eq!(engine.get_custom_order(), vec!["4", "3", "2", "1", "5"]);
+
+        // Hidden videos keep their place; the list stays in sort order
+        engine.move_videos(1, 2, 0).unwrap();
+        assert_eq!(engine.get_custom_order(), vec!["4", "1", "5", "3", "2"]);
+        let mut resorted = engine.filtered_indices.clone();
+        engine.sort_engine.sort_subset(&engine.videos, &mut resorted);
+        assert_eq!(resorted, engine.filtered_indices);
+        assert_eq!(engine.filtered_indices, vec![0, 4, 2]);
+
+        assert!(engine.move_videos(2, 2, 0).is_err());
+    }
+
+    #[test]
+    fn test_set_sort_mode_by_name() {
+        let mut engine = create_engine(vec![
+            create_test_video("1", "a", 100),
+            create_test_video("2", "a", 300),
+            create_test_video("3", "a", 200),
+        ]);
+
+        engine.set_sort_mode("date".to_string()).unwrap();
+        assert_eq!(engine.filtered_indices, vec![1, 2, 0]);
+
+        // Keeps the weights set earlier, clock included
+        engine.sort_engine.set_weighted_shuffle(ShuffleWeights {
+            now: Some(1),
+            ..Default::default()
+        });
+        engine.set_sort_mode("date".to_string()).unwrap();
+        engine.set_sort_mode("weighted_shuffle".to_string()).unwrap();
+        assert_eq!(engine.sort_engine.mode(), SortMode::WeightedShuffle);
+
+        engine.set_sort_mode("none".to_string()).unwrap();
+        assert_eq!(engine.sort_engine.mode(), SortMode::None);
+
+        // Unknown names are an error rather than silently unsorting
+        assert!(parse_sort_mode("newest").is_err());
+        assert_eq!(parse_sort_mode("custom"), Ok(SortMode::Custom));
+    }
+
+    #[test]
+    fn test_resize_reports_column_changes() {
+        let mut engine = create_engine(Vec::new());
+        let config = LayoutConfig {
+            container_width: 1000.0,
+            gap: 10.0,
+            min_tile_width: 200.0,
+            ..Default::default()
+        };
+
+        let change = engine.configure_layout(config);
+        assert!(change.full_render);
+        assert_eq!(change.metrics.unwrap().columns, 4);
+
+        // Wider tiles in the same 4 columns
+        let change = engine.configure_layout(LayoutConfig { container_width: 1030.0, ..config });
+        assert!(!change.full_render);
+        assert_eq!(change.metrics.unwrap().tile_width, 250.0);
+
+        let change = engine.configure_layout(LayoutConfig { container_width: 800.0, ..config });
+        assert!(change.full_render);
+        assert_eq!(change.metrics.unwrap().columns, 3);
+    }
+
+    #[test]
+    fn test_anchor_survives_filter() {
+        let videos: Vec<VideoItem> = (0..10)
+            .map(|i| create_test_video(&i.to_string(), ["a", "b"][i % 2], 1000 - i as u64))
+            .collect();
+        let mut engine = create_engine(videos);
+        engine.sort_engine.set_mode(SortMode::Date);
+        engine.sort_filtered();
+        let b_only = FilterCriteria {
+            folders: vec!["b".to_string()],
+            ..Default::default()
+        };
+
+        // Video 4 starts the third row, half scrolled out
+        let anchored = engine.capture_anchor(250.0, 100.0, 2, None);
+        assert_eq!(anchored.as_deref(), Some("4"));
+        apply(&mut engine, b_only.clone());
+
+        // Video 5 takes its place from the second row of 1, 3 | 5, 7 | 9
+        assert_eq!(engine.restore_anchor(100.0, 100.0, 2), Some(150.0));
+        assert_eq!(engine.restore_anchor(100.0, 100.0, 2), None);
+
+        // A focused video keeps its offset at the new tile size
+        apply(&mut engine, FilterCriteria::default());
+        let anchored = engine.capture_anchor(300.0, 100.0, 2, Some("9".to_string()));
+        assert_eq!(anchored.as_deref(), Some("9"));
+        apply(&mut engine, b_only);
+        assert_eq!(engine.restore_anchor(100.0, 200.0, 2), Some(300.0));
+    }
+}
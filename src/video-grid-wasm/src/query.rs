@@ -0,0 +1,549 @@
+use serde::Serialize;
+
+/// Compiled filter query
+///
+/// Grammar (AND binds tighter than OR, juxtaposition means AND):
+///
+/// ```text
+/// query   := or
+/// or      := and (("OR" | "|") and)*
+/// and     := unary ("AND"? unary)*
+/// unary   := ("-" | "NOT") unary | primary
+/// primary := "(" or ")" | term
+/// term    := key ":" value | word | "quoted text"
+/// ```
+///
+/// e.g. `tag:cats rating:>=4 folder:"Trips" -hidden dur:<60s`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Term(Predicate),
+}
+
+/// Single test against a video
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    Tag(String),
+    Folder(String),
+    /// Case-insensitive substring of the name
+    Name(String),
+    /// Case-insensitive substring of the name or folder
+    Text(String),
+    Codec(String),
+    Favorite,
+    Hidden,
+    Rating(Comparison),
+    /// Duration in seconds
+    Duration(Comparison),
+    /// Size in bytes
+    Size(Comparison),
+    Width(Comparison),
+    Height(Comparison),
+    /// Bitrate in bits per second
+    Bitrate(Comparison),
+}
+
+/// Numeric comparison such as `>=4`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    pub op: CmpOp,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    #[inline]
+    pub fn matches(&self, value: f64) -> bool {
+        match self.op {
+            CmpOp::Eq => value == self.value,
+            CmpOp::Lt => value < self.value,
+            CmpOp::Le => value <= self.value,
+            CmpOp::Gt => value > self.value,
+            CmpOp::Ge => value >= self.value,
+        }
+    }
+}
+
+impl Expr {
+    /// Whether the query tests the hidden flag anywhere, in which case it
+    /// takes over from the default "hide hidden videos" rule
+    pub fn mentions_hidden(&self) -> bool {
+        match self {
+            Expr::And(exprs) | Expr::Or(exprs) => exprs.iter().any(Expr::mentions_hidden),
+            Expr::Not(expr) => expr.mentions_hidden(),
+            Expr::Term(predicate) => matches!(predicate, Predicate::Hidden),
+        }
+    }
+}
+
+/// Query parse error, returned to JS as `{ position, message }`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryError {
+    /// Character offset of the offending input
+    pub position: usize,
+    pub message: String,
+}
+
+impl QueryError {
+    fn new(position: usize, message: impl Into<String>) -> Self {
+        Self {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+/// Parse a query string. An empty query matches everything.
+pub fn parse(input: &str) -> Result<Expr, QueryError> {
+    let tokens = Lexer::new(input).tokenize()?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: input.chars().count(),
+    };
+
+    if parser.tokens.is_empty() {
+        return Ok(Expr::And(Vec::new()));
+    }
+
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) if token.kind == TokenKind::RParen => {
+            Err(QueryError::new(token.pos, "unmatched ')'"))
+        }
+        Some(token) => Err(QueryError::new(token.pos, "unexpected input")),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+    /// `key:value` or a bare word / quoted text (no key)
+    Term {
+        key: Option<String>,
+        value: String,
+        value_pos: usize,
+        quoted: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    pos: usize,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Lexer {
+    fn new(input: &str) -> Self {
+        Self {
+            chars: input.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn tokenize(mut self) -> Result<Vec<Token>, QueryError> {
+        let mut tokens = Vec::new();
+
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            match c {
+                c if c.is_whitespace() => {
+                    self.pos += 1;
+                }
+                '(' => {
+                    self.pos += 1;
+                    tokens.push(Token { kind: TokenKind::LParen, pos: start });
+                }
+                ')' => {
+                    self.pos += 1;
+                    tokens.push(Token { kind: TokenKind::RParen, pos: start });
+                }
+                '|' => {
+                    self.pos += 1;
+                    tokens.push(Token { kind: TokenKind::Or, pos: start });
+                }
+                '-' => {
+                    self.pos += 1;
+                    tokens.push(Token { kind: TokenKind::Not, pos: start });
+                }
+                '"' => {
+                    let value = self.read_quoted()?;
+                    tokens.push(Token {
+                        kind: TokenKind::Term {
+                            key: None,
+                            value,
+                            value_pos: start + 1,
+                            quoted: true,
+                        },
+                        pos: start,
+                    });
+                }
+                _ => {
+                    let kind = self.read_term()?;
+                    tokens.push(Token { kind, pos: start });
+                }
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Read a `"quoted string"` starting at the opening quote
+    fn read_quoted(&mut self) -> Result<String, QueryError> {
+        let start = self.pos;
+        self.pos += 1;
+
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '"' {
+                return Ok(value);
+            }
+            value.push(c);
+        }
+
+        Err(QueryError::new(start, "unterminated quote"))
+    }
+
+    /// Read a bare word, operator keyword or `key:value` term
+    fn read_term(&mut self) -> Result<TokenKind, QueryError> {
+        let start = self.pos;
+        let mut word = String::new();
+
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            }
+
+            if c == ':' && !word.is_empty() {
+                self.pos += 1;
+                let value_pos = self.pos;
+                let (value, quoted) = if self.peek() == Some('"') {
+                    (self.read_quoted()?, true)
+                } else {
+                    (self.read_word(), false)
+                };
+                return Ok(TokenKind::Term {
+                    key: Some(word.to_lowercase()),
+                    value,
+                    value_pos,
+                    quoted,
+                });
+            }
+
+            word.push(c);
+            self.pos += 1;
+        }
+
+        Ok(match word.as_str() {
+            "AND" => TokenKind::And,
+            "OR" => TokenKind::Or,
+            "NOT" => TokenKind::Not,
+            _ => TokenKind::Term {
+                key: None,
+                value: word,
+                value_pos: start,
+                quoted: false,
+            },
+        })
+    }
+
+    fn read_word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() || c == '(' || c == ')' {
+                break;
+            }
+            word.push(c);
+            self.pos += 1;
+        }
+        word
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Position reported for errors at end of input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, QueryError> {
+        let mut exprs = vec![self.parse_and()?];
+
+        while matches!(self.peek(), Some(Token { kind: TokenKind::Or, .. })) {
+            self.pos += 1;
+            exprs.push(self.parse_and()?);
+        }
+
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { Expr::Or(exprs) })
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, QueryError> {
+        let mut exprs = vec![self.parse_unary()?];
+
+        loop {
+            match self.peek().map(|token| &token.kind) {
+                Some(TokenKind::And) => {
+                    self.pos += 1;
+                    exprs.push(self.parse_unary()?);
+                }
+                Some(TokenKind::Or) | Some(TokenKind::RParen) | None => break,
+                Some(_) => exprs.push(self.parse_unary()?),
+            }
+        }
+
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { Expr::And(exprs) })
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, QueryError> {
+        if matches!(self.peek(), Some(Token { kind: TokenKind::Not, .. })) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, QueryError> {
+        let end = self.end;
+        let token = self
+            .next()
+            .ok_or_else(|| QueryError::new(end, "expected a filter term"))?;
+
+        match token.kind {
+            TokenKind::LParen => {
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token { kind: TokenKind::RParen, .. }) => Ok(expr),
+                    _ => Err(QueryError::new(token.pos, "unmatched '('")),
+                }
+            }
+            TokenKind::Term { key, value, value_pos, quoted } => {
+                parse_term(key.as_deref(), value, token.pos, value_pos, quoted).map(Expr::Term)
+            }
+            TokenKind::RParen => Err(QueryError::new(token.pos, "unmatched ')'")),
+            TokenKind::And | TokenKind::Or => {
+                Err(QueryError::new(token.pos, "expected a filter term before operator"))
+            }
+            TokenKind::Not => unreachable!("handled by parse_unary"),
+        }
+    }
+}
+
+/// Turn a lexed term into a predicate
+fn parse_term(
+    key: Option<&str>,
+    value: String,
+    key_pos: usize,
+    value_pos: usize,
+    quoted: bool,
+) -> Result<Predicate, QueryError> {
+    let Some(key) = key else {
+        if !quoted {
+            match value.to_lowercase().as_str() {
+                "hidden" => return Ok(Predicate::Hidden),
+                "fav" | "favorite" | "favorites" => return Ok(Predicate::Favorite),
+                _ => {}
+            }
+        }
+        return Ok(Predicate::Text(value.to_lowercase()));
+    };
+
+    if value.is_empty() {
+        return Err(QueryError::new(value_pos, format!("missing value for '{}'", key)));
+    }
+
+    let predicate = match key {
+        "tag" => Predicate::Tag(value),
+        "folder" => Predicate::Folder(value),
+        "name" => Predicate::Name(value.to_lowercase()),
+        "codec" => Predicate::Codec(value.to_lowercase()),
+        "is" => match value.to_lowercase().as_str() {
+            "hidden" => Predicate::Hidden,
+            "fav" | "favorite" | "favorites" => Predicate::Favorite,
+            _ => {
+                return Err(QueryError::new(
+                    value_pos,
+                    format!("unknown flag '{}', expected 'favorite' or 'hidden'", value),
+                ))
+            }
+        },
+        "rating" | "stars" => {
+            let comparison = parse_comparison(&value, value_pos, &[])?;
+            if !(0.0..=5.0).contains(&comparison.value) {
+                return Err(QueryError::new(value_pos, "rating must be between 0 and 5"));
+            }
+            Predicate::Rating(comparison)
+        }
+        "dur" | "duration" => Predicate::Duration(parse_comparison(
+            &value,
+            value_pos,
+            &[("s", 1.0), ("sec", 1.0), ("m", 60.0), ("min", 60.0), ("h", 3600.0)],
+        )?),
+        "size" => Predicate::Size(parse_comparison(
+            &value,
+            value_pos,
+            &[
+                ("b", 1.0),
+                ("kb", 1024.0),
+                ("mb", 1024.0 * 1024.0),
+                ("gb", 1024.0 * 1024.0 * 1024.0),
+            ],
+        )?),
+        "width" | "w" => Predicate::Width(parse_comparison(&value, value_pos, &[])?),
+        "height" | "h" => Predicate::Height(parse_comparison(&value, value_pos, &[])?),
+        "bitrate" => Predicate::Bitrate(parse_comparison(
+            &value,
+            value_pos,
+            &[("k", 1e3), ("kbps", 1e3), ("m", 1e6), ("mbps", 1e6)],
+        )?),
+        _ => {
+            return Err(QueryError::new(key_pos, format!("unknown filter '{}'", key)));
+        }
+    };
+
+    Ok(predicate)
+}
+
+/// Parse `[op]number[unit]`, e.g. `>=4`, `<60s`, `1.5gb`
+fn parse_comparison(
+    value: &str,
+    value_pos: usize,
+    units: &[(&str, f64)],
+) -> Result<Comparison, QueryError> {
+    let (op, rest) = if let Some(rest) = value.strip_prefix(">=") {
+        (CmpOp::Ge, rest)
+    } else if let Some(rest) = value.strip_prefix("<=") {
+        (CmpOp::Le, rest)
+    } else if let Some(rest) = value.strip_prefix('>') {
+        (CmpOp::Gt, rest)
+    } else if let Some(rest) = value.strip_prefix('<') {
+        (CmpOp::Lt, rest)
+    } else if let Some(rest) = value.strip_prefix('=') {
+        (CmpOp::Eq, rest)
+    } else {
+        (CmpOp::Eq, value)
+    };
+
+    let number_pos = value_pos + (value.len() - rest.len());
+    let split = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(rest.len());
+    let (number, unit) = rest.split_at(split);
+
+    let number: f64 = number
+        .parse()
+        .map_err(|_| QueryError::new(number_pos, format!("expected a number, found '{}'", rest)))?;
+
+    let multiplier = if unit.is_empty() {
+        1.0
+    } else {
+        let unit_lower = unit.to_lowercase();
+        units
+            .iter()
+            .find(|(name, _)| *name == unit_lower)
+            .map(|&(_, multiplier)| multiplier)
+            .ok_or_else(|| {
+                QueryError::new(number_pos + split, format!("unknown unit '{}'", unit))
+            })?
+    };
+
+    Ok(Comparison {
+        op,
+        value: number * multiplier,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_example_query() {
+        let expr = parse(r#"tag:cats rating:>=4 folder:"Trips" -hidden dur:<60s"#).unwrap();
+
+        assert_eq!(
+            expr,
+            Expr::And(vec![
+                Expr::Term(Predicate::Tag("cats".to_string())),
+                Expr::Term(Predicate::Rating(Comparison { op: CmpOp::Ge, value: 4.0 })),
+                Expr::Term(Predicate::Folder("Trips".to_string())),
+                Expr::Not(Box::new(Expr::Term(Predicate::Hidden))),
+                Expr::Term(Predicate::Duration(Comparison { op: CmpOp::Lt, value: 60.0 })),
+            ])
+        );
+        assert!(expr.mentions_hidden());
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        // AND binds tighter than OR
+        let expr = parse("tag:a tag:b OR tag:c").unwrap();
+        assert!(matches!(expr, Expr::Or(ref exprs) if exprs.len() == 2));
+
+        let expr = parse("tag:a (tag:b OR tag:c)").unwrap();
+        assert!(matches!(expr, Expr::And(ref exprs) if exprs.len() == 2));
+    }
+
+    #[test]
+    fn test_parse_units() {
+        let expr = parse("dur:>2m size:<1.5gb").unwrap();
+        assert_eq!(
+            expr,
+            Expr::And(vec![
+                Expr::Term(Predicate::Duration(Comparison { op: CmpOp::Gt, value: 120.0 })),
+                Expr::Term(Predicate::Size(Comparison {
+                    op: CmpOp::Lt,
+                    value: 1.5 * 1024.0 * 1024.0 * 1024.0,
+                })),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("tag:a (tag:b").unwrap_err().position, 6);
+        assert_eq!(parse("tag:a )").unwrap_err().position, 6);
+        assert_eq!(parse("rating:>=x").unwrap_err().position, 9);
+        assert_eq!(parse("dur:10y").unwrap_err().message, "unknown unit 'y'");
+        assert_eq!(parse("tag:a bogus:1").unwrap_err().position, 6);
+        assert_eq!(parse("tag:a OR").unwrap_err().position, 8);
+        assert_eq!(parse("\"unterminated").unwrap_err().position, 0);
+        assert_eq!(parse("folder:\"Trips").unwrap_err().position, 7);
+    }
+}
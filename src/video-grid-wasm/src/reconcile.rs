@@ -1,3 +1,4 @@
+use crate::layout::{GridLayout, Section};
 use crate::types::{DomOperation, ReconciliationResult, VideoItem, Viewport};
 use ahash::{AHashMap, AHashSet};
 
@@ -5,6 +6,8 @@ use ahash::{AHashMap, AHashSet};
 pub struct DomReconciler {
     current_visible: Vec<String>,
     current_range: (usize, usize),
+    /// Section headers in the DOM
+    current_headers: Vec<Section>,
 }
 
 impl DomReconciler {
@@ -12,6 +15,7 @@ impl DomReconciler {
         Self {
             current_visible: Vec::new(),
             current_range: (0, 0),
+            current_headers: Vec::new(),
         }
     }
 
@@ -25,6 +29,68 @@ impl DomReconciler {
         // Calculate new visible range
         let (start_idx, end_idx) = viewport.calculate_visible_range(filtered_indices.len());
 
+        let total_rows = filtered_indices.len().div_ceil(viewport.items_per_row);
+
+        // Headers from an earlier sectioned layout go away
+        let mut operations = self.calculate_header_operations(&[]);
+        operations.extend(self.update_visible(all_videos, filtered_indices, start_idx, end_idx));
+
+        ReconciliationResult {
+            operations,
+            total_items: filtered_indices.len(),
+            visible_start: start_idx,
+            visible_end: end_idx,
+            content_height: total_rows as f64 * viewport.item_height,
+            tiles: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Calculate DOM operations for a row layout (sections, justified
+    /// rows), including the section headers entering and leaving the
+    /// viewport
+    pub fn reconcile_layout(
+        &mut self,
+        all_videos: &[VideoItem],
+        filtered_indices: &[usize],
+        layout: &GridLayout,
+        viewport: &Viewport,
+    ) -> ReconciliationResult {
+        let range = layout.visible_range(viewport);
+        let headers = &layout.sections()[range.headers.clone()];
+
+        let mut operations = self.calculate_header_operations(headers);
+        let (start_idx, end_idx) = (range.start, range.end);
+        operations.extend(self.update_visible(all_videos, filtered_indices, start_idx, end_idx));
+
+        let (tiles, headers) = if layout.has_geometry() {
+            (
+                layout.tile_geometry(all_videos, filtered_indices, range.rows),
+                layout.header_geometry(range.headers),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        ReconciliationResult {
+            operations,
+            total_items: filtered_indices.len(),
+            visible_start: start_idx,
+            visible_end: end_idx,
+            content_height: layout.height(),
+            tiles,
+            headers,
+        }
+    }
+
+    /// Switch to a new visible range, returning the video operations
+    fn update_visible(
+        &mut self,
+        all_videos: &[VideoItem],
+        filtered_indices: &[usize],
+        start_idx: usize,
+        end_idx: usize,
+    ) -> Vec<DomOperation> {
         // Get new visible video IDs
         let new_visible: Vec<String> = filtered_indices[start_idx..end_idx]
             .iter()
@@ -38,12 +104,33 @@ impl DomReconciler {
         self.current_visible = new_visible;
         self.current_range = (start_idx, end_idx);
 
-        ReconciliationResult {
-            operations,
-            total_items: filtered_indices.len(),
-            visible_start: start_idx,
-            visible_end: end_idx,
+        operations
+    }
+
+    /// Add headers that came into view and remove those that left.
+    /// A header whose section moved is removed and added again.
+    fn calculate_header_operations(&mut self, headers: &[Section]) -> Vec<DomOperation> {
+        let same = |a: &Section, b: &Section| a.id == b.id && a.start == b.start && a.top == b.top;
+
+        let mut operations: Vec<DomOperation> = self
+            .current_headers
+            .iter()
+            .filter(|old| !headers.iter().any(|new| same(old, new)))
+            .map(|old| DomOperation::RemoveHeader { section_id: old.id.clone() })
+            .collect();
+
+        for section in headers {
+            if !self.current_headers.iter().any(|old| same(old, section)) {
+                operations.push(DomOperation::AddHeader {
+                    section_id: section.id.clone(),
+                    index: section.start,
+                    top: section.top,
+                });
+            }
         }
+
+        self.current_headers = headers.to_vec();
+        operations
     }
 
     /// Calculate minimal set of DOM operations
@@ -109,6 +196,7 @@ impl DomReconciler {
     pub fn reset(&mut self) {
         self.current_visible.clear();
         self.current_range = (0, 0);
+        self.current_headers.clear();
     }
 }
 
@@ -119,6 +207,7 @@ impl Default for DomReconciler {
 }
 
 /// Helper to batch DOM operations for efficiency
+#[allow(dead_code)]
 pub struct OperationBatcher {
     adds: Vec<DomOperation>,
     removes: Vec<DomOperation>,
@@ -126,6 +215,7 @@ pub struct OperationBatcher {
     updates: Vec<DomOperation>,
 }
 
+#[allow(dead_code)]
 impl OperationBatcher {
     pub fn new() -> Self {
         Self {
@@ -138,8 +228,10 @@ impl OperationBatcher {
 
     pub fn add_operation(&mut self, op: DomOperation) {
         match op {
-            DomOperation::Add { .. } => self.adds.push(op),
-            DomOperation::Remove { .. } => self.removes.push(op),
+            DomOperation::Add { .. } | DomOperation::AddHeader { .. } => self.adds.push(op),
+            DomOperation::Remove { .. } | DomOperation::RemoveHeader { .. } => {
+                self.removes.push(op)
+            }
             DomOperation::Move { .. } => self.moves.push(op),
             DomOperation::Update { .. } => self.updates.push(op),
         }
@@ -168,6 +260,8 @@ impl Default for OperationBatcher {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::layout::{RowSizing, SectionGrouping};
+    use crate::types::LayoutConfig;
 
     fn create_test_video(id: &str) -> VideoItem {
         VideoItem {
@@ -176,15 +270,7 @@ mod tests {
             path: format!("/path/{}.mp4", id),
             folder: None,
             size: 1024,
-            last_modified: 0,
-            duration: None,
-            width: None,
-            height: None,
-            resolution: None,
-            codec: None,
-            bitrate: None,
-            is_favorite: false,
-            is_hidden: false,
+            ..Default::default()
         }
     }
 
@@ -227,13 +313,13 @@ mod tests {
             create_test_video("3"),
         ];
 
-        // First reconcile with all videos
+        // First reconcile with all videos (one per row)
         let indices = vec![0, 1, 2];
         let viewport = Viewport {
             scroll_top: 0.0,
             viewport_height: 900.0,
             item_height: 300.0,
-            items_per_row: 3,
+            items_per_row: 1,
             buffer_rows: 1,
         };
 
@@ -244,7 +330,7 @@ mod tests {
             scroll_top: 0.0,
             viewport_height: 300.0,
             item_height: 300.0,
-            items_per_row: 3,
+            items_per_row: 1,
             buffer_rows: 0,
         };
 
@@ -255,6 +341,58 @@ mod tests {
         assert!(removes > 0);
     }
 
+    #[test]
+    fn test_reconcile_section_headers() {
+        let mut videos: Vec<VideoItem> =
+            (0..6).map(|i| create_test_video(&i.to_string())).collect();
+        for (video, folder) in videos.iter_mut().zip(["a", "a", "a", "b", "b", "c"]) {
+            video.folder = Some(folder.to_string());
+        }
+        let indices: Vec<usize> = (0..videos.len()).collect();
+        let viewport = |scroll_top| Viewport {
+            scroll_top,
+            viewport_height: 150.0,
+            item_height: 100.0,
+            items_per_row: 2,
+            buffer_rows: 0,
+        };
+        let sizing = RowSizing::Uniform { item_height: 100.0, items_per_row: 2 };
+        let grouping = Some(SectionGrouping::Folder);
+        let config = LayoutConfig::default();
+        let layout = GridLayout::build(grouping, &videos, &indices, 50.0, sizing, config);
+        let mut reconciler = DomReconciler::new();
+
+        // Header a and the first row of a
+        let result = reconciler.reconcile_layout(&videos, &indices, &layout, &viewport(0.0));
+        assert_eq!(result.content_height, 550.0);
+        assert_eq!((result.visible_start, result.visible_end), (0, 2));
+        assert!(matches!(
+            &result.operations[0],
+            DomOperation::AddHeader { section_id, index: 0, top }
+                if section_id == "a" && *top == 0.0
+        ));
+
+        // Scrolling to b drops header a and adds header b
+        let result = reconciler.reconcile_layout(&videos, &indices, &layout, &viewport(200.0));
+        assert_eq!((result.visible_start, result.visible_end), (2, 5));
+        assert!(matches!(
+            &result.operations[0],
+            DomOperation::RemoveHeader { section_id } if section_id == "a"
+        ));
+        assert!(matches!(
+            &result.operations[1],
+            DomOperation::AddHeader { section_id, index: 3, top }
+                if section_id == "b" && *top == 250.0
+        ));
+
+        // The uniform grid removes leftover headers
+        let result = reconciler.reconcile(&videos, &indices, &viewport(0.0));
+        assert!(matches!(
+            &result.operations[0],
+            DomOperation::RemoveHeader { section_id } if section_id == "b"
+        ));
+    }
+
     #[test]
     fn test_operation_batching() {
         let mut batcher = OperationBatcher::new();
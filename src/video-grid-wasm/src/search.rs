@@ -0,0 +1,378 @@
+use crate::types::VideoItem;
+use ahash::AHashMap;
+
+/// Relevance scores keyed by video index
+pub type SearchScores = AHashMap<usize, f32>;
+
+/// Field weights: a hit in the name counts more than one in the path
+const NAME_WEIGHT: f32 = 1.0;
+const FOLDER_WEIGHT: f32 = 0.8;
+const PATH_WEIGHT: f32 = 0.6;
+
+/// Similarity of a prefix (non-exact) match
+const PREFIX_SIMILARITY: f32 = 0.9;
+
+/// Minimum trigram similarity for a fuzzy token match
+const FUZZY_THRESHOLD: f32 = 0.4;
+
+type Trigram = [char; 3];
+
+/// Fuzzy full-text index over video name, folder and path
+///
+/// Each distinct token is indexed once: by text (sorted, for prefix
+/// lookups while typing) and by padded trigrams (for typo tolerance).
+/// Postings map tokens back to the videos and the best field they
+/// appear in.
+pub struct SearchIndex {
+    /// Token id -> token text
+    tokens: Vec<String>,
+    token_ids: AHashMap<String, u32>,
+    /// Token ids ordered by text
+    sorted_tokens: Vec<u32>,
+    /// Trigram -> token ids containing it
+    trigrams: AHashMap<Trigram, Vec<u32>>,
+    /// Token id -> trigram count
+    trigram_counts: Vec<u32>,
+    /// Token id -> (video index, field weight)
+    postings: Vec<Vec<(u32, f32)>>,
+}
+
+impl SearchIndex {
+    pub fn new() -> Self {
+        Self {
+            tokens: Vec::new(),
+            token_ids: AHashMap::new(),
+            sorted_tokens: Vec::new(),
+            trigrams: AHashMap::new(),
+            trigram_counts: Vec::new(),
+            postings: Vec::new(),
+        }
+    }
+
+    /// Rebuild the index for a video collection
+    pub fn rebuild(&mut self, videos: &[VideoItem]) {
+        *self = Self::new();
+
+        for (idx, video) in videos.iter().enumerate() {
+            for (token_id, weight) in self.video_tokens(video) {
+                // Ascending video order keeps postings sorted
+                self.postings[token_id as usize].push((idx as u32, weight));
+            }
+        }
+
+        let mut sorted: Vec<u32> = (0..self.tokens.len() as u32).collect();
+        sorted.sort_unstable_by(|&a, &b| self.tokens[a as usize].cmp(&self.tokens[b as usize]));
+        self.sorted_tokens = sorted;
+    }
+
+    /// Index one video, new or changed, at `idx`
+    pub fn add_video(&mut self, idx: usize, video: &VideoItem) {
+        let known_tokens = self.tokens.len();
+        for (token_id, weight) in self.video_tokens(video) {
+            let postings = &mut self.postings[token_id as usize];
+            let position = postings.partition_point(|&(other, _)| other < idx as u32);
+            postings.insert(position, (idx as u32, weight));
+        }
+
+        // Slot tokens seen for the first time into the sorted vocabulary
+        for token_id in known_tokens as u32..self.tokens.len() as u32 {
+            let token = &self.tokens[token_id as usize];
+            let position = self
+                .sorted_tokens
+                .partition_point(|&id| &self.tokens[id as usize] < token);
+            self.sorted_tokens.insert(position, token_id);
+        }
+    }
+
+    /// Drop one video from the postings. Its tokens stay in the vocabulary.
+    pub fn remove_video(&mut self, idx: usize, video: &VideoItem) {
+        for token_id in self.video_tokens(video).into_keys() {
+            let postings = &mut self.postings[token_id as usize];
+            let found = postings.binary_search_by_key(&(idx as u32), |&(other, _)| other);
+            if let Ok(position) = found {
+                postings.remove(position);
+            }
+        }
+    }
+
+    /// Re-index a video whose name, folder or path changed
+    pub fn update_video(&mut self, idx: usize, before: &VideoItem, after: &VideoItem) {
+        if before.name != after.name || before.folder != after.folder || before.path != after.path
+        {
+            self.remove_video(idx, before);
+            self.add_video(idx, after);
+        }
+    }
+
+    /// Token ids of a video with the weight of the best field they occur in
+    fn video_tokens(&mut self, video: &VideoItem) -> AHashMap<u32, f32> {
+        let mut fields: AHashMap<u32, f32> = AHashMap::new();
+        let sources = [
+            (Some(video.name.as_str()), NAME_WEIGHT),
+            (video.folder.as_deref(), FOLDER_WEIGHT),
+            (Some(video.path.as_str()), PATH_WEIGHT),
+        ];
+
+        for (text, weight) in sources {
+            for token in tokenize(text.unwrap_or_default()) {
+                let token_id = self.intern(token);
+                let best = fields.entry(token_id).or_insert(weight);
+                *best = best.max(weight);
+            }
+        }
+        fields
+    }
+
+    /// Search for videos matching every token of the query.
+    /// Returns relevance scores in `(0, 1]` keyed by video index.
+    pub fn search(&self, query: &str) -> SearchScores {
+        let query_tokens = tokenize(query);
+        let mut scores: Option<SearchScores> = None;
+
+        for query_token in &query_tokens {
+            let mut token_scores = SearchScores::new();
+            for (token_id, similarity) in self.match_token(query_token) {
+                for &(idx, weight) in &self.postings[token_id as usize] {
+                    let score = token_scores.entry(idx as usize).or_insert(0.0);
+                    *score = score.max(similarity * weight);
+                }
+            }
+
+            // Every query token has to match (AND)
+            scores = Some(match scores {
+                None => token_scores,
+                Some(mut acc) => {
+                    acc.retain(|idx, score| match token_scores.get(idx) {
+                        Some(token_score) => {
+                            *score += token_score;
+                            true
+                        }
+                        None => false,
+                    });
+                    acc
+                }
+            });
+        }
+
+        let mut scores = scores.unwrap_or_default();
+        let count = query_tokens.len() as f32;
+        for score in scores.values_mut() {
+            *score /= count;
+        }
+        scores
+    }
+
+    /// Score of the indexed video at `idx` for a query, as `search` would
+    /// give it, or `None` when it does not match
+    pub fn score_video(&self, query: &str, idx: usize) -> Option<f32> {
+        let query_tokens = tokenize(query);
+        if query_tokens.is_empty() {
+            return None;
+        }
+
+        let mut total = 0.0;
+        for query_token in &query_tokens {
+            let mut best: Option<f32> = None;
+            for (token_id, similarity) in self.match_token(query_token) {
+                let postings = &self.postings[token_id as usize];
+                let found = postings.binary_search_by_key(&(idx as u32), |&(other, _)| other);
+                if let Ok(position) = found {
+                    let score = similarity * postings[position].1;
+                    best = Some(best.map_or(score, |best| best.max(score)));
+                }
+            }
+            // Every query token has to match (AND)
+            total += best?;
+        }
+        Some(total / query_tokens.len() as f32)
+    }
+
+        /// Find indexed tokens similar to a query token
+    fn match_token(&self, query_token: &str) -> AHashMap<u32, f32> {
+        let mut matches = AHashMap::new();
+
+        // Prefix matches (search-as-you-type)
+        let start = self
+            .sorted_tokens
+            .partition_point(|&id| self.tokens[id as usize].as_str() < query_token);
+        for &token_id in &self.sorted_tokens[start..] {
+            let token = &self.tokens[token_id as usize];
+            if !token.starts_with(query_token) {
+                break;
+            }
+            let similarity = if token == query_token { 1.0 } else { PREFIX_SIMILARITY };
+            matches.insert(token_id, similarity);
+        }
+
+        // Fuzzy matches via shared trigrams (Dice coefficient)
+        let query_trigrams = trigrams(query_token);
+        if query_trigrams.len() < 3 {
+            return matches;
+        }
+
+        let mut shared: AHashMap<u32, u32> = AHashMap::new();
+        for trigram in &query_trigrams {
+            if let Some(token_ids) = self.trigrams.get(trigram) {
+                for &token_id in token_ids {
+                    *shared.entry(token_id).or_insert(0) += 1;
+                }
+            }
+        }
+
+        for (token_id, count) in shared {
+            let total = query_trigrams.len() as u32 + self.trigram_counts[token_id as usize];
+            let similarity = 2.0 * count as f32 / total as f32;
+            if similarity >= FUZZY_THRESHOLD {
+                let best = matches.entry(token_id).or_insert(0.0);
+                *best = best.max(similarity);
+            }
+        }
+
+        matches
+    }
+
+    /// Get the id of a token, adding it to the index if new
+    fn intern(&mut self, token: String) -> u32 {
+        if let Some(&id) = self.token_ids.get(&token) {
+            return id;
+        }
+
+        let id = self.tokens.len() as u32;
+        let token_trigrams = trigrams(&token);
+        for trigram in &token_trigrams {
+            self.trigrams.entry(*trigram).or_default().push(id);
+        }
+        self.trigram_counts.push(token_trigrams.len() as u32);
+        self.postings.push(Vec::new());
+        self.token_ids.insert(token.clone(), id);
+        self.tokens.push(token);
+        id
+    }
+}
+
+impl Default for SearchIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Split text into lowercase alphanumeric tokens
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_lowercase())
+        .collect()
+}
+
+/// Distinct trigrams of a token padded with word boundary markers
+fn trigrams(token: &str) -> Vec<Trigram> {
+    let chars: Vec<char> = std::iter::once('^')
+        .chain(token.chars())
+        .chain(std::iter::once('$'))
+        .collect();
+
+    let mut result: Vec<Trigram> = chars
+        .windows(3)
+        .map(|w| [w[0], w[1], w[2]])
+        .collect();
+    result.sort_unstable();
+    result.dedup();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_video(id: &str, name: &str, folder: Option<&str>) -> VideoItem {
+        VideoItem {
+            id: id.to_string(),
+            name: name.to_string(),
+            path: format!("/library/{}/{}", folder.unwrap_or("root"), name),
+            folder: folder.map(|s| s.to_string()),
+            size: 1024,
+            ..Default::default()
+        }
+    }
+
+    fn build_index() -> SearchIndex {
+        let videos = vec![
+            create_test_video("1", "Summer_Vacation_Beach.mp4", Some("Trips")),
+            create_test_video("2", "birthday-party.mov", Some("Family")),
+            create_test_video("3", "beach sunset.mp4", Some("Vacation 2023")),
+        ];
+        let mut index = SearchIndex::new();
+        index.rebuild(&videos);
+        index
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Summer_Vacation-2023.MP4"),
+            vec!["summer", "vacation", "2023", "mp4"]
+        );
+    }
+
+    #[test]
+    fn test_score_video_matches_search() {
+        let mut index = build_index();
+        let before = create_test_video("2", "birthday-party.mov", Some("Family"));
+        let after = create_test_video("2", "beach party.mov", Some("Family"));
+        index.update_video(1, &before, &after);
+
+        for query in ["beach", "vacaton beach", "party", "birth", ""] {
+            let scores = index.search(query);
+            for idx in 0..3 {
+                assert_eq!(index.score_video(query, idx), scores.get(&idx).copied(), "{query}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_prefix_search() {
+        let index = build_index();
+
+        let scores = index.search("birth");
+        assert_eq!(scores.len(), 1);
+        assert!(scores.contains_key(&1));
+    }
+
+    #[test]
+    fn test_typo_tolerance() {
+        let index = build_index();
+
+        let scores = index.search("vacaton");
+        assert!(scores.contains_key(&0));
+        assert!(scores.contains_key(&2));
+
+        // Name hit scores higher than folder hit
+        assert!(scores[&0] > scores[&2]);
+    }
+
+    #[test]
+    fn test_all_tokens_required() {
+        let index = build_index();
+
+        let scores = index.search("beach sunset");
+        assert_eq!(scores.len(), 1);
+        assert!(scores.contains_key(&2));
+    }
+
+    #[test]
+    fn test_incremental_updates() {
+        let mut index = build_index();
+
+        let mut renamed = create_test_video("2", "birthday-party.mov", Some("Family"));
+        renamed.name = "graduation.mov".to_string();
+        renamed.path = "/library/Family/graduation.mov".to_string();
+        let before = create_test_video("2", "birthday-party.mov", Some("Family"));
+        index.update_video(1, &before, &renamed);
+        index.add_video(3, &create_test_video("4", "zebra crossing.mp4", None));
+
+        assert!(index.search("birthday").is_empty());
+        assert!(index.search("gradu").contains_key(&1));
+        assert!(index.search("zeb").contains_key(&3));
+        assert_eq!(index.search("family").len(), 1);
+    }
+}
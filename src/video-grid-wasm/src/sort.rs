@@ -1,114 +1,676 @@
-use crate::types::{SortMode, VideoItem};
+use crate::bitset::BitSet;
+use crate::types::{ShuffleWeights, SortField, SortKey, SortMode, SortOrder, VideoItem};
+use ahash::{AHashMap, AHashSet};
+use std::cmp::Ordering;
+use std::iter::Peekable;
+
+/// Sorted permutations kept for recently used sort specs
+const CACHED_SPECS: usize = 4;
 
 /// High-performance sorting engine with cached sort keys
+///
+/// Key-based modes compare videos key by key and finally by id, so the
+/// order is total and the same across runs. The named modes are presets
+/// over the same keys.
+///
+/// Shuffle orders by a pseudo-random key derived from the seed and the
+/// video id alone, so a seed always reproduces the same order and videos
+/// keep their relative order when others are filtered in or out. The
+/// weighted shuffle turns the same random number into a weighted draw
+/// (Efraimidis-Spirakis), so it keeps all of these properties.
+///
+/// The custom order follows an explicit list of ids. Videos not in the
+/// list come after the listed ones, newest first, so new arrivals show up
+/// at the end of a hand-arranged collection.
+///
+/// Sorting a subset of the collection goes through a permutation of the
+/// whole collection, built once per sort spec from precomputed key
+/// columns and cached until the videos change.
 pub struct SortEngine {
     mode: SortMode,
+    /// Keys for `SortMode::Keys`, or the preset of a named mode
+    keys: Vec<SortKey>,
+    shuffle_seed: u64,
+    weights: ShuffleWeights,
+    /// Video ids in the custom order
+    custom_order: Vec<String>,
+    /// Video id -> position in `custom_order`
+    custom_rank: AHashMap<String, u32>,
+    /// Bumped whenever the custom order changes
+    custom_revision: u64,
+    /// Most recently used first
+    caches: Vec<SortCache>,
+}
+
+/// Sorted permutation of the collection under one sort spec
+struct SortCache {
+    spec: CacheSpec,
+    /// Video indices in sorted order
+    order: Vec<u32>,
+    /// Video index -> position in `order`
+    rank: Vec<u32>,
+}
+
+/// The parts of the engine state a sorted permutation depends on
+#[derive(Debug, Clone, PartialEq)]
+enum CacheSpec {
+    Keys(Vec<SortKey>),
+    Shuffle(u64),
+    WeightedShuffle(u64, ShuffleWeights),
+    Custom(u64),
 }
 
 impl SortEngine {
     pub fn new(mode: SortMode) -> Self {
-        Self { mode }
+        Self {
+            mode,
+            keys: mode.preset_keys(),
+            shuffle_seed: 0,
+            weights: ShuffleWeights::default(),
+            custom_order: Vec::new(),
+            custom_rank: AHashMap::new(),
+            custom_revision: 0,
+            caches: Vec::new(),
+        }
     }
 
     pub fn set_mode(&mut self, mode: SortMode) {
         self.mode = mode;
+        self.keys = mode.preset_keys();
     }
 
-    /// Sort videos by current mode
-    /// Returns indices in sorted order for zero-copy sorting
-    pub fn sort_indices(&self, videos: &[VideoItem]) -> Vec<usize> {
-        let mut indices: Vec<usize> = (0..videos.len()).collect();
+    /// Sort by an ordered list of keys. No keys keeps collection order.
+    pub fn set_keys(&mut self, keys: Vec<SortKey>) {
+        self.mode = if keys.is_empty() { SortMode::None } else { SortMode::Keys };
+        self.keys = keys;
+    }
+
+    pub fn mode(&self) -> SortMode {
+        self.mode
+    }
 
+    pub fn keys(&self) -> &[SortKey] {
+        &self.keys
+    }
+
+    /// First key of a key-based order, `None` for shuffles, the custom
+    /// order and no order
+    pub fn leading_key(&self) -> Option<&SortKey> {
         match self.mode {
-            SortMode::Folder => {
-                self.sort_by_folder(&mut indices, videos);
-            }
-            SortMode::Date => {
-                self.sort_by_date(&mut indices, videos);
+            SortMode::Shuffle
+            | SortMode::WeightedShuffle
+            | SortMode::Custom
+            | SortMode::None => None,
+            _ => self.keys.first(),
+        }
+    }
+
+    pub fn set_shuffle_seed(&mut self, seed: u64) {
+        self.shuffle_seed = seed;
+    }
+
+    pub fn shuffle_seed(&self) -> u64 {
+        self.shuffle_seed
+    }
+
+    /// Switch to the weighted shuffle
+    pub fn set_weighted_shuffle(&mut self, weights: ShuffleWeights) {
+        self.set_mode(SortMode::WeightedShuffle);
+        self.weights = weights;
+    }
+
+    /// Switch to the weighted shuffle with the current weights, taking the
+    /// clock from `now` if they do not pin one yet
+    pub fn resume_weighted_shuffle(&mut self, now: impl FnOnce() -> u64) {
+        self.set_mode(SortMode::WeightedShuffle);
+        self.weights.now = self.weights.now.or_else(|| Some(now()));
+    }
+
+    /// Replace the custom order. Duplicate ids keep their first position.
+    pub fn set_custom_order(&mut self, ids: Vec<String>) {
+        let mut seen = AHashSet::with_capacity(ids.len());
+        self.custom_order = ids
+            .into_iter()
+            .filter(|id| seen.insert(id.clone()))
+            .collect();
+        self.custom_order_changed();
+    }
+
+    #[cfg(test)]
+    pub fn custom_order(&self) -> &[String] {
+        &self.custom_order
+    }
+
+    /// Switch to the custom order and list every video in it, so any of
+    /// them can be moved. Coming from another mode, the custom order
+    /// starts out as the current order.
+    pub fn pin_custom_order(&mut self, videos: &[VideoItem]) {
+        if self.mode != SortMode::Custom {
+            let ids = self
+                .sort_indices(videos)
+                .into_iter()
+                .map(|idx| videos[idx].id.clone())
+                .collect();
+            self.set_mode(SortMode::Custom);
+            self.set_custom_order(ids);
+            return;
+        }
+
+        let unlisted = self.unlisted_ids(videos);
+        if !unlisted.is_empty() {
+            self.custom_order.extend(unlisted);
+            self.custom_order_changed();
+        }
+    }
+
+    /// Move videos in the custom order to just before `next`, or else
+    /// just after `previous`, or else to the end. Neighbours that are
+    /// not in the list are ignored.
+    pub fn move_custom(&mut self, ids: &[String], next: Option<&str>, previous: Option<&str>) {
+        self.custom_order.retain(|id| !ids.contains(id));
+        self.custom_order_changed();
+
+        let position = match (next, previous) {
+            (Some(next), _) if self.custom_rank.contains_key(next) => self.custom_rank[next],
+            (_, Some(previous)) if self.custom_rank.contains_key(previous) => {
+                self.custom_rank[previous] + 1
             }
-            SortMode::Shuffle => {
-                // Fisher-Yates shuffle using js_sys::Math::random()
-                self.fisher_yates_shuffle(&mut indices);
+            _ => self.custom_order.len() as u32,
+        } as usize;
+        self.custom_order.splice(position..position, ids.iter().cloned());
+        self.custom_order_changed();
+    }
+
+    /// The custom order for persisting: the listed ids (including videos
+    /// not currently loaded), then the unlisted videos in display order
+    pub fn export_custom_order(&self, videos: &[VideoItem]) -> Vec<String> {
+        let mut ids = self.custom_order.clone();
+        ids.extend(self.unlisted_ids(videos));
+        ids
+    }
+
+    /// Ids of videos not in the custom order, in the order they follow it
+    fn unlisted_ids(&self, videos: &[VideoItem]) -> Vec<String> {
+        let keys = SortMode::Custom.preset_keys();
+        let mut unlisted: Vec<&VideoItem> = videos
+            .iter()
+            .filter(|video| !self.custom_rank.contains_key(&video.id))
+            .collect();
+        unlisted.sort_by(|a, b| compare_keys(&keys, a, b));
+        unlisted.into_iter().map(|video| video.id.clone()).collect()
+    }
+
+    /// Re-index the custom order after changing it
+    fn custom_order_changed(&mut self) {
+        self.custom_rank = self
+            .custom_order
+            .iter()
+            .enumerate()
+            .map(|(position, id)| (id.clone(), position as u32))
+            .collect();
+        self.custom_revision += 1;
+    }
+
+    /// Drop cached permutations; call whenever videos or their sort
+    /// values change
+    pub fn invalidate(&mut self) {
+        self.caches.clear();
+    }
+
+    /// Drop cached permutations that a change to one video may reorder
+    pub fn video_changed(&mut self, before: &VideoItem, after: &VideoItem) {
+        self.caches.retain(|cache| match &cache.spec {
+            CacheSpec::Keys(keys) => keys.iter().all(|key| compare_key(key, before, after).is_eq()),
+            CacheSpec::Shuffle(_) => before.id == after.id,
+            CacheSpec::WeightedShuffle(_, weights) => {
+                before.id == after.id
+                    && shuffle_weight(weights, before) == shuffle_weight(weights, after)
             }
-            SortMode::None => {
-                // Keep original order
+            CacheSpec::Custom(_) => {
+                before.id == after.id
+                    && SortMode::Custom
+                        .preset_keys()
+                        .iter()
+                        .all(|key| compare_key(key, before, after).is_eq())
             }
+        });
+    }
+
+    /// Sort videos by current mode
+    /// Returns indices in sorted order for zero-copy sorting
+    pub fn sort_indices(&self, videos: &[VideoItem]) -> Vec<usize> {
+        match self.cache_spec() {
+            Some(spec) => self
+                .build_order(&spec, videos)
+                .into_iter()
+                .map(|idx| idx as usize)
+                .collect(),
+            // Keep original order
+            None => (0..videos.len()).collect(),
         }
+    }
 
-        indices
+    /// Put a set of video indices into sort order using the cached
+    /// permutation: by rank for small sets, by walking the permutation
+    /// for large ones
+    pub fn sort_subset(&mut self, videos: &[VideoItem], indices: &mut [usize]) {
+        let Some(spec) = self.cache_spec() else {
+            indices.sort_unstable();
+            return;
+        };
+        let cache = self.cache(spec, videos);
+
+        if indices.len() * 8 < videos.len() {
+            indices.sort_unstable_by_key(|&idx| cache.rank[idx]);
+        } else {
+            let mut members = BitSet::new(videos.len());
+            for &idx in indices.iter() {
+                members.insert(idx);
+            }
+            let sorted = cache
+                .order
+                .iter()
+                .map(|&idx| idx as usize)
+                .filter(|&idx| members.contains(idx));
+            for (slot, idx) in indices.iter_mut().zip(sorted) {
+                *slot = idx;
+            }
+        }
     }
 
     /// Sort videos in place
+    #[allow(dead_code)]
     pub fn sort_videos(&self, videos: &mut [VideoItem]) {
         match self.mode {
-            SortMode::Folder => {
-                videos.sort_by(|a, b| {
-                    // Sort by folder (ABC), then by date (newest first) within folder
-                    let folder_cmp = self.compare_folders(a, b);
-                    if folder_cmp != std::cmp::Ordering::Equal {
-                        folder_cmp
-                    } else {
-                        // Within same folder, newest first
-                        b.last_modified.cmp(&a.last_modified)
-                    }
-                });
-            }
-            SortMode::Date => {
-                // Newest first
-                videos.sort_by(|a, b| b.last_modified.cmp(&a.last_modified));
-            }
-            SortMode::Shuffle => {
-                // For WASM: Use simpler shuffle (client will handle via JS)
-                // Don't shuffle server-side to avoid rng issues
-            }
             SortMode::None => {
                 // No sorting
             }
+            _ => videos.sort_by(|a, b| self.compare(a, b)),
+        }
+    }
+
+    /// Compare two videos key by key (or by shuffle key), then by id.
+    /// None has no order and compares equal.
+    pub fn compare(&self, a: &VideoItem, b: &VideoItem) -> Ordering {
+        match self.mode {
+            SortMode::None => Ordering::Equal,
+            SortMode::Shuffle => shuffle_key(self.shuffle_seed, &a.id)
+                .cmp(&shuffle_key(self.shuffle_seed, &b.id))
+                .then_with(|| a.id.cmp(&b.id)),
+            // Highest draw first
+            SortMode::WeightedShuffle => self
+                .weighted_draw(b)
+                .total_cmp(&self.weighted_draw(a))
+                .then_with(|| a.id.cmp(&b.id)),
+            // Listed videos by position, then the rest by the preset keys
+            SortMode::Custom => missing_last(
+                self.custom_rank.get(&a.id),
+                self.custom_rank.get(&b.id),
+                SortOrder::Asc,
+            )
+            .then_with(|| compare_keys(&self.keys, a, b)),
+            _ => compare_keys(&self.keys, a, b),
         }
     }
 
-    /// Sort by folder, then date within folder
-    fn sort_by_folder(&self, indices: &mut [usize], videos: &[VideoItem]) {
-        indices.sort_by(|&a, &b| {
-            let video_a = &videos[a];
-            let video_b = &videos[b];
+    /// Weighted random draw `ln(u) / weight` (monotonic in `u^(1/weight)`)
+    fn weighted_draw(&self, video: &VideoItem) -> f64 {
+        // Uniform in (0, 1] from the top 53 bits of the shuffle key
+        let key = shuffle_key(self.shuffle_seed, &video.id);
+        let uniform = ((key >> 11) + 1) as f64 / (1u64 << 53) as f64;
+        uniform.ln() / shuffle_weight(&self.weights, video)
+    }
 
-            let folder_cmp = self.compare_folders(video_a, video_b);
-            if folder_cmp != std::cmp::Ordering::Equal {
-                folder_cmp
-            } else {
-                // Within same folder, newest first
-                video_b.last_modified.cmp(&video_a.last_modified)
+    /// Spec of the current mode, `None` when there is no order
+    fn cache_spec(&self) -> Option<CacheSpec> {
+        match self.mode {
+            SortMode::None => None,
+            SortMode::Shuffle => Some(CacheSpec::Shuffle(self.shuffle_seed)),
+            SortMode::WeightedShuffle => {
+                Some(CacheSpec::WeightedShuffle(self.shuffle_seed, self.weights))
             }
-        });
+            SortMode::Custom => Some(CacheSpec::Custom(self.custom_revision)),
+            _ => Some(CacheSpec::Keys(self.keys.clone())),
+        }
     }
 
-    /// Sort by date (newest first)
-    fn sort_by_date(&self, indices: &mut [usize], videos: &[VideoItem]) {
-        indices.sort_by(|&a, &b| {
-            videos[b].last_modified.cmp(&videos[a].last_modified)
+    /// Cached permutation for a spec, building it on a miss
+    fn cache(&mut self, spec: CacheSpec, videos: &[VideoItem]) -> &SortCache {
+        let hit = self
+            .caches
+            .iter()
+            .position(|cache| cache.spec == spec && cache.rank.len() == videos.len());
+
+        let cache = match hit {
+            Some(position) => self.caches.remove(position),
+            None => {
+                let order = self.build_order(&spec, videos);
+                let mut rank = vec![0; order.len()];
+                for (position, &idx) in order.iter().enumerate() {
+                    rank[idx as usize] = position as u32;
+                }
+                SortCache { spec, order, rank }
+            }
+        };
+
+        self.caches.truncate(CACHED_SPECS - 1);
+        self.caches.insert(0, cache);
+        &self.caches[0]
+    }
+
+    /// Sort the whole collection on precomputed key columns, then by id.
+    /// Agrees with `compare`.
+    fn build_order(&self, spec: &CacheSpec, videos: &[VideoItem]) -> Vec<u32> {
+        let columns: Vec<Vec<u128>> = match spec {
+            CacheSpec::Keys(keys) => keys.iter().map(|key| key_column(key, videos)).collect(),
+            CacheSpec::Shuffle(seed) => vec![videos
+                .iter()
+                .map(|video| shuffle_key(*seed, &video.id) as u128)
+                .collect()],
+            // Highest draw first
+            CacheSpec::WeightedShuffle(..) => vec![videos
+                .iter()
+                .map(|video| !sortable_f64(self.weighted_draw(video)) as u128)
+                .collect()],
+            CacheSpec::Custom(_) => {
+                let ranks = videos
+                    .iter()
+                    .map(|video| match self.custom_rank.get(&video.id) {
+                        Some(&rank) => rank as u128,
+                        None => u128::MAX,
+                    })
+                    .collect();
+                std::iter::once(ranks)
+                    .chain(self.keys.iter().map(|key| key_column(key, videos)))
+                    .collect()
+            }
+        };
+
+        let mut order: Vec<u32> = (0..videos.len() as u32).collect();
+        order.sort_unstable_by(|&a, &b| {
+            columns
+                .iter()
+                .map(|column| column[a as usize].cmp(&column[b as usize]))
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or_else(|| videos[a as usize].id.cmp(&videos[b as usize].id))
         });
+        order
     }
+}
+
+/// Column of one sort key over the collection: ascending column values
+/// give the key's order, with missing values last
+fn key_column(key: &SortKey, videos: &[VideoItem]) -> Vec<u128> {
+    let direct = |value: u64| match key.order {
+        SortOrder::Asc => value,
+        SortOrder::Desc => !value,
+    };
+    // Missing values sort after every present one in either direction
+    let optional = |value: Option<u64>| match value {
+        Some(value) => direct(value) as u128,
+        None => 1 << 64,
+    };
 
-    /// Compare folder names with null handling
-    #[inline]
-    fn compare_folders(&self, a: &VideoItem, b: &VideoItem) -> std::cmp::Ordering {
-        match (&a.folder, &b.folder) {
-            (Some(fa), Some(fb)) => fa.cmp(fb),
-            (Some(_), None) => std::cmp::Ordering::Less,
-            (None, Some(_)) => std::cmp::Ordering::Greater,
-            (None, None) => std::cmp::Ordering::Equal,
+    match key.field {
+        SortField::Folder => {
+            let ranks = string_ranks(videos.iter().filter_map(|v| v.folder.as_deref()), str::cmp);
+            videos
+                .iter()
+                .map(|video| optional(video.folder.as_deref().map(|folder| ranks[folder])))
+                .collect()
         }
+        SortField::Name => {
+            let ranks = string_ranks(videos.iter().map(|v| v.name.as_str()), natural_cmp);
+            videos
+                .iter()
+                .map(|video| direct(ranks[video.name.as_str()]) as u128)
+                .collect()
+        }
+        SortField::Date => videos.iter().map(|v| direct(v.last_modified) as u128).collect(),
+        SortField::Size => videos.iter().map(|v| direct(v.size) as u128).collect(),
+        SortField::Duration => videos
+            .iter()
+            .map(|v| optional(v.duration.map(sortable_f64)))
+            .collect(),
+        SortField::Resolution => videos.iter().map(|v| optional(pixel_count(v))).collect(),
+        SortField::Bitrate => videos
+            .iter()
+            .map(|v| optional(v.bitrate.map(u64::from)))
+            .collect(),
+        SortField::Rating => videos
+            .iter()
+            .map(|v| optional(v.rating.map(u64::from)))
+            .collect(),
     }
+}
+
+/// Rank of each distinct string under an ordering; equal strings share
+/// a rank
+fn string_ranks<'v>(
+    values: impl Iterator<Item = &'v str>,
+    cmp: impl Fn(&str, &str) -> Ordering,
+) -> AHashMap<&'v str, u64> {
+    let mut distinct: Vec<&str> = values.collect();
+    distinct.sort_unstable_by(|a, b| cmp(a, b));
+    distinct.dedup();
 
-    /// Fisher-Yates shuffle (in-place)
-    fn fisher_yates_shuffle(&self, slice: &mut [usize]) {
-        for i in (1..slice.len()).rev() {
-            // Use a simple deterministic "random" for now
-            // In real usage, JS will handle shuffle
-            let j = (i * 7919 + 31) % (i + 1);
-            slice.swap(i, j);
+    let mut ranks = AHashMap::with_capacity(distinct.len());
+    let mut rank = 0;
+    for (i, value) in distinct.iter().enumerate() {
+        if i > 0 && cmp(distinct[i - 1], value).is_ne() {
+            rank += 1;
         }
+        ranks.insert(*value, rank);
+    }
+    ranks
+}
+
+/// Map an `f64` to a `u64` with the same order (`-0.0` equals `0.0`)
+#[inline]
+fn sortable_f64(value: f64) -> u64 {
+    let bits = (value + 0.0).to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | 1 << 63
+    }
+}
+
+/// Weight of a video in the weighted shuffle, see `ShuffleWeights`
+pub fn shuffle_weight(weights: &ShuffleWeights, video: &VideoItem) -> f64 {
+    const DAY_MS: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+    let rating = video.rating.map_or(0.5, |rating| rating.min(5) as f64 / 5.0);
+    let favorite = if video.is_favorite { 1.0 } else { 0.0 };
+    let unseen = match (video.last_viewed, weights.now) {
+        (None, _) => 1.0,
+        (Some(viewed), Some(now)) => {
+            let days = now.saturating_sub(viewed) as f64 / DAY_MS;
+            (days / weights.unseen_days.max(f64::MIN_POSITIVE)).min(1.0)
+        }
+        (Some(_), None) => 0.0,
+    };
+
+    let weight = 1.0
+        + weights.rating * rating
+        + weights.favorite * favorite
+        + weights.unseen * unseen;
+    weight.max(f64::MIN_POSITIVE)
+}
+
+impl SortMode {
+    /// Keys a named mode sorts by
+    pub fn preset_keys(self) -> Vec<SortKey> {
+        use SortField::*;
+        use SortOrder::*;
+
+        let keys: &[(SortField, SortOrder)] = match self {
+            // Folder A-Z, newest first within a folder, then by name
+            SortMode::Folder => &[(Folder, Asc), (Date, Desc), (Name, Asc)],
+            SortMode::Date => &[(Date, Desc)],
+            SortMode::Name => &[(Name, Asc)],
+            // Largest first, ties by name
+            SortMode::Size => &[(Size, Desc), (Name, Asc)],
+            SortMode::Duration => &[(Duration, Desc), (Name, Asc)],
+            SortMode::Resolution => &[(Resolution, Desc), (Name, Asc)],
+            SortMode::Bitrate => &[(Bitrate, Desc), (Name, Asc)],
+            SortMode::Rating => &[(Rating, Desc), (Name, Asc)],
+            // Videos missing from the custom order: newest first
+            SortMode::Custom => &[(Date, Desc), (Name, Asc)],
+            SortMode::Keys
+            | SortMode::Shuffle
+            | SortMode::WeightedShuffle
+            | SortMode::None => &[],
+        };
+
+        keys.iter()
+            .map(|&(field, order)| SortKey { field, order })
+            .collect()
+    }
+}
+
+/// Compare two videos key by key, then by id
+fn compare_keys(keys: &[SortKey], a: &VideoItem, b: &VideoItem) -> Ordering {
+    keys.iter()
+        .map(|key| compare_key(key, a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.id.cmp(&b.id))
+}
+
+/// Compare two videos on one key. Videos missing the value go last in
+/// either direction.
+fn compare_key(key: &SortKey, a: &VideoItem, b: &VideoItem) -> Ordering {
+    let order = key.order;
+    match key.field {
+        SortField::Folder => missing_last(a.folder.as_deref(), b.folder.as_deref(), order),
+        SortField::Date => order.apply(a.last_modified.cmp(&b.last_modified)),
+        SortField::Name => order.apply(natural_cmp(&a.name, &b.name)),
+        SortField::Size => order.apply(a.size.cmp(&b.size)),
+        SortField::Duration => missing_last(a.duration, b.duration, order),
+        SortField::Resolution => missing_last(pixel_count(a), pixel_count(b), order),
+        SortField::Bitrate => missing_last(a.bitrate, b.bitrate, order),
+        SortField::Rating => missing_last(a.rating, b.rating, order),
+    }
+}
+
+/// Order known values by direction and missing values after them
+#[inline]
+fn missing_last<T: PartialOrd>(a: Option<T>, b: Option<T>, order: SortOrder) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => order.apply(a.partial_cmp(&b).unwrap_or(Ordering::Equal)),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Pseudo-random sort key of a video under a shuffle seed
+///
+/// The id is hashed with FNV-1a (stable across builds and platforms,
+/// unlike the process-seeded hashers) and mixed with the seed through
+/// SplitMix64.
+pub fn shuffle_key(seed: u64, id: &str) -> u64 {
+    let hash = id.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    splitmix64(splitmix64(seed) ^ hash)
+}
+
+/// SplitMix64 output function
+#[inline]
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Frame area, known only when both dimensions are
+#[inline]
+fn pixel_count(video: &VideoItem) -> Option<u64> {
+    Some(video.width? as u64 * video.height? as u64)
+}
+
+/// Compare names the way people read them: case- and diacritic-insensitive,
+/// with runs of digits compared by value ("clip2" < "clip10").
+/// Names that only differ in case, accents or leading zeros fall back to
+/// a plain comparison so the order stays total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut chars_a = folded_chars(a).peekable();
+    let mut chars_b = folded_chars(b).peekable();
+
+    loop {
+        let ordering = match (chars_a.peek(), chars_b.peek()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                compare_numbers(&mut chars_a, &mut chars_b)
+            }
+            (Some(&x), Some(&y)) => {
+                chars_a.next();
+                chars_b.next();
+                x.cmp(&y)
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Compare the digit runs at the front of both iterators by value
+fn compare_numbers(
+    a: &mut Peekable<impl Iterator<Item = char>>,
+    b: &mut Peekable<impl Iterator<Item = char>>,
+) -> Ordering {
+    while a.next_if_eq(&'0').is_some() {}
+    while b.next_if_eq(&'0').is_some() {}
+
+    // Longer run is the bigger number; otherwise the first differing digit
+    let mut first_difference = Ordering::Equal;
+    loop {
+        match (a.next_if(char::is_ascii_digit), b.next_if(char::is_ascii_digit)) {
+            (Some(x), Some(y)) => first_difference = first_difference.then(x.cmp(&y)),
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (None, None) => return first_difference,
+        }
+    }
+}
+
+/// Lowercase characters with diacritics removed. Handles precomposed
+/// Latin letters and decomposed (NFD) input such as macOS file names.
+fn folded_chars(text: &str) -> impl Iterator<Item = char> + '_ {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !('\u{0300}'..='\u{036f}').contains(c))
+        .map(strip_diacritic)
+}
+
+/// Base letter of a lowercase Latin-1 / Latin Extended-A letter
+fn strip_diacritic(c: char) -> char {
+    match c {
+        'à'..='å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'ĥ' | 'ħ' => 'h',
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+        'ĵ' => 'j',
+        'ķ' => 'k',
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+        'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'ŕ' | 'ŗ' | 'ř' => 'r',
+        'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'ţ' | 'ť' | 'ŧ' => 't',
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'ŵ' => 'w',
+        'ý' | 'ÿ' | 'ŷ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        _ => c,
     }
 }
 
@@ -130,14 +692,7 @@ mod tests {
             folder: folder.map(|s| s.to_string()),
             size: 1024,
             last_modified: timestamp,
-            duration: None,
-            width: None,
-            height: None,
-            resolution: None,
-            codec: None,
-            bitrate: None,
-            is_favorite: false,
-            is_hidden: false,
+            ..Default::default()
         }
     }
 
@@ -191,4 +746,275 @@ mod tests {
         assert_eq!(indices[1], 2); // video 3
         assert_eq!(indices[2], 0); // video 1
     }
+
+    #[test]
+    fn test_natural_cmp() {
+        let mut names = vec![
+            "clip10.mp4",
+            "Clip2.mp4",
+            "clip1.mp4",
+            "Élan.mp4",
+            "eve.mp4",
+            "clip02b",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            vec!["clip1.mp4", "Clip2.mp4", "clip02b", "clip10.mp4", "Élan.mp4", "eve.mp4"]
+        );
+
+        // Precomposed and decomposed accents fold the same way
+        assert_eq!(natural_cmp("cafe\u{301} 2", "café 10"), Ordering::Less);
+        // Order stays total for names that only differ in case or accents
+        assert_ne!(natural_cmp("café", "CAFE"), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_folder_ties_sorted_by_name() {
+        let mut videos = vec![
+            create_test_video("1", Some("a"), 100),
+            create_test_video("2", Some("a"), 100),
+            create_test_video("3", Some("a"), 100),
+        ];
+        videos[0].name = "clip10.mp4".to_string();
+        videos[1].name = "clip9.mp4".to_string();
+        videos[2].name = "Clip1.mp4".to_string();
+
+        let engine = SortEngine::new(SortMode::Folder);
+        engine.sort_videos(&mut videos);
+
+        let ids: Vec<&str> = videos.iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec!["3", "2", "1"]);
+    }
+
+    #[test]
+    fn test_metadata_sorts_put_missing_last() {
+        let mut videos = vec![
+            create_test_video("1", None, 0),
+            create_test_video("2", None, 0),
+            create_test_video("3", None, 0),
+            create_test_video("4", None, 0),
+        ];
+        videos[0].duration = Some(30.0);
+        videos[1].duration = Some(120.0);
+        videos[3].duration = Some(30.0);
+        videos[0].width = Some(1920);
+        videos[0].height = Some(1080);
+        videos[1].width = Some(3840);
+        videos[2].height = Some(2160);
+        videos[2].rating = Some(2);
+        videos[3].rating = Some(5);
+        videos[1].size = 4096;
+
+        let order = |mode: SortMode| -> Vec<usize> { SortEngine::new(mode).sort_indices(&videos) };
+
+        // Equal durations fall back to name order
+        assert_eq!(order(SortMode::Duration), vec![1, 0, 3, 2]);
+        // Only video 1 has both dimensions
+        assert_eq!(order(SortMode::Resolution), vec![0, 1, 2, 3]);
+        assert_eq!(order(SortMode::Rating), vec![3, 2, 0, 1]);
+        assert_eq!(order(SortMode::Size), vec![1, 0, 2, 3]);
+    }
+
+    #[test]
+    fn test_sort_keys_with_directions() {
+        let mut videos = vec![
+            create_test_video("4", Some("b"), 100),
+            create_test_video("3", Some("a"), 100),
+            create_test_video("2", Some("a"), 100),
+            create_test_video("1", None, 100),
+        ];
+        videos[0].rating = Some(3);
+        videos[1].rating = Some(1);
+        videos[2].rating = Some(5);
+        for video in &mut videos {
+            video.name = "same.mp4".to_string();
+        }
+
+        let mut engine = SortEngine::new(SortMode::None);
+        engine.set_keys(vec![
+            SortKey { field: SortField::Folder, order: SortOrder::Desc },
+            SortKey { field: SortField::Rating, order: SortOrder::Asc },
+        ]);
+        let ids: Vec<&str> = engine
+            .sort_indices(&videos)
+            .into_iter()
+            .map(|idx| videos[idx].id.as_str())
+            .collect();
+        // Folder Z-A with the missing folder last, rating low to high
+        assert_eq!(ids, vec!["4", "3", "2", "1"]);
+
+        // Full ties fall back to the id
+        engine.set_keys(vec![SortKey { field: SortField::Name, order: SortOrder::Asc }]);
+        let ids: Vec<&str> = engine
+            .sort_indices(&videos)
+            .into_iter()
+            .map(|idx| videos[idx].id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_seeded_shuffle() {
+        let videos: Vec<VideoItem> = (0..50)
+            .map(|i| create_test_video(&i.to_string(), None, 0))
+            .collect();
+
+        let mut engine = SortEngine::new(SortMode::Shuffle);
+        engine.set_shuffle_seed(42);
+        let order = engine.sort_indices(&videos);
+        assert_ne!(order, (0..50).collect::<Vec<_>>());
+
+        // Same seed reproduces the order, another seed changes it
+        assert_eq!(engine.sort_indices(&videos), order);
+        engine.set_shuffle_seed(43);
+        assert_ne!(engine.sort_indices(&videos), order);
+
+        // Filtering out videos keeps the relative order of the rest
+        engine.set_shuffle_seed(42);
+        let subset: Vec<VideoItem> = videos.iter().step_by(3).cloned().collect();
+        let subset_ids: Vec<&str> = engine
+            .sort_indices(&subset)
+            .into_iter()
+            .map(|idx| subset[idx].id.as_str())
+            .collect();
+        let expected: Vec<&str> = order
+            .iter()
+            .map(|&idx| videos[idx].id.as_str())
+            .filter(|id| subset_ids.contains(id))
+            .collect();
+        assert_eq!(subset_ids, expected);
+    }
+
+    #[test]
+    fn test_resume_weighted_shuffle_pins_clock_once() {
+        let mut engine = SortEngine::new(SortMode::None);
+        engine.resume_weighted_shuffle(|| 42);
+        assert_eq!(engine.mode(), SortMode::WeightedShuffle);
+        assert_eq!(engine.weights.now, Some(42));
+
+        // An already pinned clock is kept
+        engine.set_mode(SortMode::Date);
+        engine.resume_weighted_shuffle(|| 99);
+        assert_eq!(engine.weights.now, Some(42));
+    }
+
+    #[test]
+    fn test_weighted_shuffle_favors_heavy_videos() {
+        const DAY: u64 = 24 * 60 * 60 * 1000;
+        let now = 1000 * DAY;
+
+        // Half the videos are 5-star favorites not seen in months, half are
+        // unrated clips watched today
+        let videos: Vec<VideoItem> = (0..200)
+            .map(|i| {
+                let mut video = create_test_video(&i.to_string(), None, 0);
+                if i % 2 == 0 {
+                    video.rating = Some(5);
+                    video.is_favorite = true;
+                    video.last_viewed = Some(now - 90 * DAY);
+                } else {
+                    video.last_viewed = Some(now);
+                }
+                video
+            })
+            .collect();
+
+        let weights = ShuffleWeights {
+            now: Some(now),
+            ..Default::default()
+        };
+        assert_eq!(shuffle_weight(&weights, &videos[0]), 6.0);
+        assert_eq!(shuffle_weight(&weights, &videos[1]), 2.0);
+
+        let mut engine = SortEngine::new(SortMode::None);
+        engine.set_shuffle_seed(7);
+        engine.set_weighted_shuffle(weights);
+        let order = engine.sort_indices(&videos);
+        assert_eq!(engine.sort_indices(&videos), order);
+
+        let heavy_in_top = order[..50].iter().filter(|&&idx| idx % 2 == 0).count();
+        assert!(heavy_in_top > 35, "only {} heavy videos in the top 50", heavy_in_top);
+    }
+
+    #[test]
+    fn test_custom_order() {
+        let videos = vec![
+            create_test_video("a", None, 100),
+            create_test_video("b", None, 300),
+            create_test_video("c", None, 200),
+            create_test_video("d", None, 400),
+        ];
+        let ids = |order: Vec<usize>| -> Vec<&str> {
+            order.into_iter().map(|idx| videos[idx].id.as_str()).collect()
+        };
+
+        // Listed videos first, the rest newest first; unknown ids are kept
+        let mut engine = SortEngine::new(SortMode::Custom);
+        engine.set_custom_order(vec!["c".into(), "gone".into(), "a".into(), "c".into()]);
+        assert_eq!(ids(engine.sort_indices(&videos)), vec!["c", "a", "d", "b"]);
+        assert_eq!(
+            engine.export_custom_order(&videos),
+            vec!["c", "gone", "a", "d", "b"]
+        );
+
+        engine.move_custom(&["b".into()], Some("a"), Some("c"));
+        assert_eq!(ids(engine.sort_indices(&videos)), vec!["c", "b", "a", "d"]);
+        engine.move_custom(&["c".into(), "b".into()], None, Some("d"));
+        assert_eq!(ids(engine.sort_indices(&videos)), vec!["a", "c", "b", "d"]);
+
+        // Pinning from another mode starts from its order
+        let mut engine = SortEngine::new(SortMode::Date);
+        engine.pin_custom_order(&videos);
+        assert_eq!(engine.mode(), SortMode::Custom);
+        assert_eq!(engine.custom_order(), ["d", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn test_cached_order_matches_compare() {
+        let videos: Vec<VideoItem> = (0..300)
+            .map(|i| {
+                let mut video = create_test_video(
+                    &format!("{:03}", (i * 37) % 300),
+                    (i % 4 != 0).then(|| ["b", "a", "c"][i % 3]),
+                    (i % 5) as u64,
+                );
+                video.name = format!("Clip{}", i % 17);
+                video.duration = (i % 3 != 0).then_some((i % 7) as f64 * 1.5);
+                video.rating = (i % 2 == 0).then_some((i % 5) as u8 + 1);
+                video
+            })
+            .collect();
+
+        let mut engine = SortEngine::new(SortMode::Folder);
+        engine.set_shuffle_seed(3);
+        let modes = [
+            SortMode::Folder,
+            SortMode::Date,
+            SortMode::Name,
+            SortMode::Duration,
+            SortMode::Rating,
+            SortMode::Shuffle,
+            SortMode::WeightedShuffle,
+            SortMode::Custom,
+        ];
+        let custom: Vec<String> = (0..100).map(|i| format!("{:03}", (i * 7) % 300)).collect();
+        engine.set_custom_order(custom);
+
+        for mode in modes {
+            engine.set_mode(mode);
+            let mut expected: Vec<usize> = (0..videos.len()).collect();
+            expected.sort_by(|&a, &b| engine.compare(&videos[a], &videos[b]));
+            assert_eq!(engine.sort_indices(&videos), expected, "{:?}", mode);
+
+            // Small and large subsets keep the same relative order
+            for step in [1, 3, 11] {
+                let mut subset: Vec<usize> = (0..videos.len()).step_by(step).collect();
+                engine.sort_subset(&videos, &mut subset);
+                let in_order: Vec<usize> =
+                    expected.iter().copied().filter(|idx| idx % step == 0).collect();
+                assert_eq!(subset, in_order, "{:?} step {}", mode, step);
+            }
+        }
+    }
 }
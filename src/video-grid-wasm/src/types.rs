@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 /// Video metadata for grid rendering
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[wasm_bindgen]
 pub struct VideoItem {
     #[wasm_bindgen(skip)]
@@ -25,6 +25,15 @@ pub struct VideoItem {
     pub bitrate: Option<u32>,
     pub is_favorite: bool,
     pub is_hidden: bool,
+    #[wasm_bindgen(skip)]
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Star rating (1-5), `None` when unrated
+    #[serde(default)]
+    pub rating: Option<u8>,
+    /// When the video was last watched (ms since epoch), `None` if never
+    #[serde(default)]
+    pub last_viewed: Option<u64>,
 }
 
 #[wasm_bindgen]
@@ -48,26 +57,344 @@ impl VideoItem {
     pub fn folder(&self) -> Option<String> {
         self.folder.clone()
     }
+
+    #[wasm_bindgen(getter)]
+    pub fn tags(&self) -> Vec<String> {
+        self.tags.clone()
+    }
+}
+
+/// A video sent to `upsertVideo(s)`: the scanner's metadata plus any user
+/// data the caller sets. User data left out keeps the engine's value, so a
+/// rescan never resets tags, ratings or flags.
+#[derive(Debug, Clone, Deserialize)]
+pub struct VideoUpdate {
+    pub id: String,
+    pub name: String,
+    pub path: String,
+    pub folder: Option<String>,
+    pub size: u64,
+    pub last_modified: u64,
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub resolution: Option<String>,
+    pub codec: Option<String>,
+    pub bitrate: Option<u32>,
+    #[serde(default)]
+    pub is_favorite: Option<bool>,
+    #[serde(default)]
+    pub is_hidden: Option<bool>,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+    /// Star rating (1-5), 0 clears it
+    #[serde(default)]
+    pub rating: Option<u8>,
+    /// Last watched (ms since epoch), 0 clears it
+    #[serde(default)]
+    pub last_viewed: Option<u64>,
+}
+
+impl VideoUpdate {
+    /// Copy the scanner fields, and the user data that was set, onto `video`
+    pub fn apply(self, video: &mut VideoItem) {
+        video.name = self.name;
+        video.path = self.path;
+        video.folder = self.folder;
+        video.size = self.size;
+        video.last_modified = self.last_modified;
+        video.duration = self.duration;
+        video.width = self.width;
+        video.height = self.height;
+        video.resolution = self.resolution;
+        video.codec = self.codec;
+        video.bitrate = self.bitrate;
+
+        if let Some(is_favorite) = self.is_favorite {
+            video.is_favorite = is_favorite;
+        }
+        if let Some(is_hidden) = self.is_hidden {
+            video.is_hidden = is_hidden;
+        }
+        if let Some(tags) = self.tags {
+            video.tags = tags;
+        }
+        if let Some(rating) = self.rating {
+            video.rating = (rating > 0).then_some(rating);
+        }
+        if let Some(last_viewed) = self.last_viewed {
+            video.last_viewed = (last_viewed > 0).then_some(last_viewed);
+        }
+    }
+
+    /// New video, with user data not set left empty
+    pub fn into_video(self) -> VideoItem {
+        VideoItem {
+            id: self.id,
+            name: self.name,
+            path: self.path,
+            folder: self.folder,
+            size: self.size,
+            last_modified: self.last_modified,
+            duration: self.duration,
+            width: self.width,
+            height: self.height,
+            resolution: self.resolution,
+            codec: self.codec,
+            bitrate: self.bitrate,
+            is_favorite: self.is_favorite.unwrap_or(false),
+            is_hidden: self.is_hidden.unwrap_or(false),
+            tags: self.tags.unwrap_or_default(),
+            rating: self.rating.filter(|&rating| rating > 0),
+            last_viewed: self.last_viewed.filter(|&last_viewed| last_viewed > 0),
+        }
+    }
 }
 
 /// Filter criteria for videos
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FilterCriteria {
     pub folder: Option<String>,
+    /// Show videos in any of these folders (in addition to `folder`)
+    #[serde(default)]
+    pub folders: Vec<String>,
+    /// Selected and excluded folders also cover their subfolders
+    #[serde(default)]
+    pub include_subfolders: bool,
+    /// Never show videos in these folders
+    #[serde(default)]
+    pub exclude_folders: Vec<String>,
     pub favorites_only: bool,
     pub hidden_only: bool,
     pub show_hidden: bool,
+    /// Video must carry every one of these tags (AND)
+    #[serde(default)]
+    pub tags_all: Vec<String>,
+    /// Video must carry at least one of these tags (OR)
+    #[serde(default)]
+    pub tags_any: Vec<String>,
+    /// Video must carry none of these tags (NOT)
+    #[serde(default)]
+    pub tags_exclude: Vec<String>,
+    /// Minimum star rating, inclusive
+    pub min_rating: Option<u8>,
+    /// Maximum star rating, inclusive
+    pub max_rating: Option<u8>,
+    /// Treatment of unrated videos. When unset they are excluded if a
+    /// rating bound is given and included otherwise.
+    pub unrated: Option<MissingPolicy>,
+    /// Fuzzy search over name, folder and path
+    pub search: Option<String>,
+    /// Order search results by relevance instead of the sort mode
+    #[serde(default)]
+    pub sort_by_relevance: bool,
+    /// Filter query, e.g. `tag:cats rating:>=4 -hidden`
+    pub query: Option<String>,
+    /// Duration range in seconds
+    #[serde(default)]
+    pub duration: RangeFilter,
+    /// File size range in bytes
+    #[serde(default)]
+    pub size: RangeFilter,
+    /// Width range in pixels
+    #[serde(default)]
+    pub width: RangeFilter,
+    /// Height range in pixels
+    #[serde(default)]
+    pub height: RangeFilter,
+    /// Bitrate range in bits per second
+    #[serde(default)]
+    pub bitrate: RangeFilter,
+    /// Allowed codecs (case-insensitive); empty allows any
+    #[serde(default)]
+    pub codecs: Vec<String>,
+    /// Rejected codecs (case-insensitive)
+    #[serde(default)]
+    pub exclude_codecs: Vec<String>,
+    /// Treatment of videos with unknown codec. When unset they are excluded
+    /// if `codecs` is non-empty and included otherwise.
+    pub unknown_codec: Option<MissingPolicy>,
+    /// Only videos modified at or after this time (ms since epoch)
+    pub modified_after: Option<u64>,
+    /// Only videos modified at or before this time (ms since epoch)
+    pub modified_before: Option<u64>,
+    /// Only videos modified within this many ms before `now`
+    pub modified_within: Option<u64>,
+    /// Only videos modified more than this many ms before `now`
+    pub modified_older_than: Option<u64>,
+    /// Reference time for relative windows (ms since epoch).
+    /// Defaults to the current time.
+    pub now: Option<u64>,
 }
 
-impl Default for FilterCriteria {
-    fn default() -> Self {
-        Self {
-            folder: None,
-            favorites_only: false,
-            hidden_only: false,
-            show_hidden: false,
+impl FilterCriteria {
+    /// Whether any relative date window needs a reference time
+    pub fn has_relative_dates(&self) -> bool {
+        self.modified_within.is_some() || self.modified_older_than.is_some()
+    }
+
+    /// Resolve absolute and relative date filters into an inclusive
+    /// `last_modified` range. Relative windows are ignored without `now`.
+    pub fn modified_range(&self, now: Option<u64>) -> (u64, u64) {
+        let mut min = self.modified_after.unwrap_or(0);
+        let mut max = self.modified_before.unwrap_or(u64::MAX);
+
+        if let Some(now) = now {
+            if let Some(window) = self.modified_within {
+                min = min.max(now.saturating_sub(window));
+            }
+            if let Some(window) = self.modified_older_than {
+                max = max.min(now.saturating_sub(window));
+            }
         }
+
+        (min, max)
+    }
+}
+
+/// Inclusive numeric range over optional video metadata
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RangeFilter {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Treatment of videos where the value is unknown. When unset they are
+    /// excluded if a bound is given and included otherwise.
+    pub missing: Option<MissingPolicy>,
+}
+
+impl RangeFilter {
+    pub fn is_bounded(&self) -> bool {
+        self.min.is_some() || self.max.is_some()
     }
+
+    #[inline]
+    pub fn contains(&self, value: f64) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+/// How a filter treats videos whose value is unknown (`None`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingPolicy {
+    /// Unknown values pass the filter
+    Include,
+    /// Unknown values fail the filter
+    Exclude,
+    /// Only unknown values pass the filter
+    Only,
+}
+
+/// Individual rules of a filter pass, in evaluation order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterRule {
+    Folder,
+    Favorites,
+    Hidden,
+    Tags,
+    Rating,
+    Duration,
+    Size,
+    Width,
+    Height,
+    Bitrate,
+    Codec,
+    Date,
+    Search,
+    Query,
+}
+
+impl FilterRule {
+    pub const ALL: [FilterRule; 14] = [
+        FilterRule::Folder,
+        FilterRule::Favorites,
+        FilterRule::Hidden,
+        FilterRule::Tags,
+        FilterRule::Rating,
+        FilterRule::Duration,
+        FilterRule::Size,
+        FilterRule::Width,
+        FilterRule::Height,
+        FilterRule::Bitrate,
+        FilterRule::Codec,
+        FilterRule::Date,
+        FilterRule::Search,
+        FilterRule::Query,
+    ];
+
+    /// Whether `FilterEngine` answers the rule from an index
+    pub fn is_indexed(self) -> bool {
+        matches!(
+            self,
+            FilterRule::Folder | FilterRule::Favorites | FilterRule::Hidden | FilterRule::Tags
+        )
+    }
+
+    /// Bit of this rule in a rejection mask
+    #[inline]
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// A rule that rejected a video
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterRejection {
+    pub rule: FilterRule,
+    pub reason: String,
+}
+
+/// Why a video is or isn't part of a filter result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterExplanation {
+    pub video_id: String,
+    /// Whether the id is part of the collection at all
+    pub found: bool,
+    pub passed: bool,
+    pub rejections: Vec<FilterRejection>,
+    /// Position in the sorted result, as if the video passed.
+    /// `None` when not found.
+    pub position: Option<usize>,
+}
+
+/// Count of one facet value, see `facets::filter_with_facets`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FacetValue {
+    pub value: String,
+    /// Videos in the current result with this value
+    pub count: usize,
+    /// Result size if this value were toggled
+    pub toggled: usize,
+}
+
+/// Facet counts computed alongside a filter pass
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FacetCounts {
+    pub total: usize,
+    pub folders: Vec<FacetValue>,
+    pub tags: Vec<FacetValue>,
+    pub ratings: Vec<FacetValue>,
+    pub resolutions: Vec<FacetValue>,
+    pub codecs: Vec<FacetValue>,
+}
+
+/// One step of an incremental filter update
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum FilterChange {
+    /// Video left the filtered list at `position`
+    Remove { position: usize, video_id: String },
+    /// Video entered the filtered list at `position`
+    Insert { position: usize, video_id: String },
+}
+
+/// Changes to the filtered list, to be applied in order (e.g. with `splice`)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FilterDelta {
+    pub changes: Vec<FilterChange>,
+    /// Filtered count after the changes
+    pub total: usize,
 }
 
 /// Sort mode for videos
@@ -75,10 +402,100 @@ impl Default for FilterCriteria {
 pub enum SortMode {
     Folder,
     Date,
+    Name,
+    // Metadata sorts: largest first, videos missing the value last
+    Size,
+    Duration,
+    Resolution,
+    Bitrate,
+    Rating,
+    /// Explicit list of `SortKey`s
+    Keys,
+    /// Seeded random order, see `SortEngine::set_shuffle_seed`
     Shuffle,
+    /// Seeded random order biased by `ShuffleWeights`
+    WeightedShuffle,
+    /// Hand-arranged order, see `SortEngine::set_custom_order`
+    Custom,
     None,
 }
 
+/// Tuning for the weighted shuffle
+///
+/// Every video has a base weight of 1 plus each factor (in `0..=1`)
+/// times its weight, and is picked first with probability proportional
+/// to the total.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShuffleWeights {
+    /// Weight of the star rating (unrated counts as the middle)
+    pub rating: f64,
+    /// Weight of being a favorite
+    pub favorite: f64,
+    /// Weight of time since last viewed; never viewed counts as fully unseen
+    pub unseen: f64,
+    /// Days without viewing after which a video counts as fully unseen
+    pub unseen_days: f64,
+    /// Reference time for `last_viewed` (ms since epoch), defaults to now
+    pub now: Option<u64>,
+}
+
+impl Default for ShuffleWeights {
+    fn default() -> Self {
+        Self {
+            rating: 2.0,
+            favorite: 1.0,
+            unseen: 2.0,
+            unseen_days: 30.0,
+            now: None,
+        }
+    }
+}
+
+/// Video attribute a sort key orders by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    Folder,
+    Date,
+    Name,
+    Size,
+    Duration,
+    /// Pixel count
+    Resolution,
+    Bitrate,
+    Rating,
+}
+
+/// Sort direction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    #[default]
+    #[serde(rename = "ASC", alias = "asc")]
+    Asc,
+    #[serde(rename = "DESC", alias = "desc")]
+    Desc,
+}
+
+impl SortOrder {
+    /// Apply the direction to an ascending comparison
+    #[inline]
+    pub fn apply(self, ordering: std::cmp::Ordering) -> std::cmp::Ordering {
+        match self {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+}
+
+/// One key of a sort specification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortKey {
+    pub field: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+}
+
 /// Viewport information for virtual scrolling
 #[derive(Debug, Clone, Copy)]
 pub struct Viewport {
@@ -91,7 +508,7 @@ pub struct Viewport {
 
 impl Viewport {
     pub fn calculate_visible_range(&self, total_items: usize) -> (usize, usize) {
-        let total_rows = (total_items + self.items_per_row - 1) / self.items_per_row;
+        let total_rows = total_items.div_ceil(self.items_per_row);
 
         // Calculate visible row range
         let start_row = (self.scroll_top / self.item_height).floor() as usize;
@@ -111,6 +528,7 @@ impl Viewport {
 
 /// State of a video element in the DOM
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
 pub(crate) enum VideoState {
     NotLoaded,
     Loading,
@@ -123,6 +541,7 @@ pub(crate) enum VideoState {
 /// Video element tracking for state management
 #[derive(Debug, Clone)]
 pub struct VideoElementState {
+    #[allow(dead_code)]
     pub video_id: String,
     pub state: VideoState,
     pub last_interaction: u64,
@@ -131,6 +550,7 @@ pub struct VideoElementState {
 }
 
 impl VideoElementState {
+    #[allow(dead_code)]
     pub fn new(video_id: String) -> Self {
         Self {
             video_id,
@@ -149,6 +569,113 @@ pub enum DomOperation {
     Remove { video_id: String },
     Move { video_id: String, from: usize, to: usize },
     Update { video_id: String, index: usize },
+    /// Section header above the video at filtered position `index`,
+    /// with its header top in px
+    AddHeader { section_id: String, index: usize, top: f64 },
+    RemoveHeader { section_id: String },
+}
+
+/// Grid geometry settings (px)
+///
+/// With a container width and a minimum tile width (or a density), the
+/// engine fits as many columns as it can and sizes the tiles itself.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+    /// Width of the grid container; 0 when unknown (no tile geometry)
+    pub container_width: f64,
+    /// Space between tiles, rows and section headers
+    pub gap: f64,
+    /// Space between the container edges and the content
+    pub padding: f64,
+    /// Narrowest tile; 0 to take columns from the caller
+    pub min_tile_width: f64,
+    /// Widest tile; 0 for no limit
+    pub max_tile_width: f64,
+    /// Tile width over height
+    pub aspect_ratio: f64,
+    /// Preset tile widths, overriding `min_tile_width` and `max_tile_width`
+    pub density: Option<Density>,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            container_width: 0.0,
+            gap: 0.0,
+            padding: 0.0,
+            min_tile_width: 0.0,
+            max_tile_width: 0.0,
+            aspect_ratio: 16.0 / 9.0,
+            density: None,
+        }
+    }
+}
+
+impl LayoutConfig {
+    /// Container width inside the padding
+    pub fn content_width(&self) -> f64 {
+        (self.container_width - 2.0 * self.padding).max(0.0)
+    }
+
+    /// Minimum and maximum tile width, from the density if set
+    pub fn tile_width_range(&self) -> (f64, f64) {
+        match self.density {
+            Some(Density::Compact) => (160.0, 240.0),
+            Some(Density::Comfortable) => (240.0, 360.0),
+            Some(Density::Spacious) => (360.0, 540.0),
+            None => (self.min_tile_width, self.max_tile_width),
+        }
+    }
+}
+
+/// Tile size presets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Density {
+    Compact,
+    Comfortable,
+    Spacious,
+}
+
+/// Column count and tile size derived from a `LayoutConfig`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GridMetrics {
+    pub columns: usize,
+    pub tile_width: f64,
+    pub tile_height: f64,
+}
+
+/// Outcome of a layout or container size change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayoutChange {
+    /// `None` when the caller supplies the columns
+    pub metrics: Option<GridMetrics>,
+    /// Tiles moved to other rows (e.g. the column count changed), so the
+    /// grid needs a full re-render rather than resized tiles
+    pub full_render: bool,
+}
+
+/// Position and size of a visible tile in the grid (px)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TileGeometry {
+    /// Position in the filtered list
+    pub index: usize,
+    pub video_id: String,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Position and size of a visible section header (px)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeaderGeometry {
+    pub section_id: String,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
 }
 
 /// Result of DOM reconciliation
@@ -158,4 +685,10 @@ pub struct ReconciliationResult {
     pub total_items: usize,
     pub visible_start: usize,
     pub visible_end: usize,
+    /// Height of the whole grid (px)
+    pub content_height: f64,
+    /// Geometry of the visible tiles, empty without a container width
+    pub tiles: Vec<TileGeometry>,
+    /// Geometry of the visible section headers
+    pub headers: Vec<HeaderGeometry>,
 }